@@ -0,0 +1,99 @@
+//! DMA 缓冲区的 cache 一致性维护
+use core::ptr::NonNull;
+
+use log::*;
+
+use crate::{flush, invalidate};
+
+/// 按 cache line 对齐维护, 覆盖 Phytium 平台上最大的 cache line
+pub const DMA_CACHE_LINE_SIZE: usize = 64;
+
+/// DMA 传输方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaDirection {
+    /// 设备从内存读取, 如写卡数据和描述符
+    ToDevice,
+    /// 设备写入内存, 如读卡数据
+    FromDevice,
+}
+
+/// 一段交给设备访问的内存, 负责传输前后的 flush/invalidate
+#[derive(Debug, Clone, Copy)]
+pub struct DmaRegion {
+    addr: NonNull<u8>,
+    size: usize,
+}
+
+impl DmaRegion {
+    /// `size` is in bytes
+    pub fn new(addr: NonNull<u8>, size: usize) -> Self {
+        DmaRegion { addr, size }
+    }
+
+    pub fn from_slice<T>(buf: &[T]) -> Self {
+        DmaRegion {
+            addr: NonNull::new(buf.as_ptr() as *mut u8).unwrap(),
+            size: core::mem::size_of_val(buf),
+        }
+    }
+
+    pub fn addr(&self) -> NonNull<u8> {
+        self.addr
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether start and end both sit on cache line boundaries
+    pub fn is_cache_aligned(&self) -> bool {
+        (self.addr.as_ptr() as usize | self.size) % DMA_CACHE_LINE_SIZE == 0
+    }
+
+    /// Hand the region over to device before transfer
+    pub fn prepare(&self, dir: DmaDirection) {
+        if self.size == 0 {
+            return;
+        }
+
+        let (start, size) = self.cache_lines();
+        match dir {
+            DmaDirection::ToDevice => flush(start, size),
+            DmaDirection::FromDevice => {
+                /* 首尾不对齐的 cache line 可能与其他数据共享, 先写回再失效, 避免丢失脏数据 */
+                if !self.is_cache_aligned() {
+                    debug!(
+                        "DMA region {:p}, size 0x{:x} not cache aligned",
+                        self.addr, self.size
+                    );
+                    flush(start, size);
+                }
+                invalidate(start, size);
+            }
+        }
+    }
+
+    /// Hand the region back to CPU after transfer
+    pub fn complete(&self, dir: DmaDirection) {
+        if self.size == 0 {
+            return;
+        }
+
+        /* 丢弃传输期间 CPU 预取进 cache 的旧数据 */
+        if dir == DmaDirection::FromDevice {
+            let (start, size) = self.cache_lines();
+            invalidate(start, size);
+        }
+    }
+
+    /// 向外扩展到完整的 cache line
+    fn cache_lines(&self) -> (NonNull<u8>, usize) {
+        let start = self.addr.as_ptr() as usize;
+        let aligned_start = start & !(DMA_CACHE_LINE_SIZE - 1);
+        let aligned_end = (start + self.size).next_multiple_of(DMA_CACHE_LINE_SIZE);
+        (
+            NonNull::new(aligned_start as *mut u8).unwrap(),
+            aligned_end - aligned_start,
+        )
+    }
+}
@@ -184,7 +184,7 @@ pub enum FioPadDelayType {
     NumOfDelayType,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FioPadDelay {
     DelayNone = 0,
     Delay1,
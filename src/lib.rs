@@ -7,20 +7,25 @@ use core::{ptr::NonNull, time::Duration};
 #[macro_use]
 mod regs;
 mod aarch;
+mod dma;
 pub mod iopad;
 pub mod mci;
 pub mod mci_host;
 pub mod osa;
 mod tools;
 
+pub use dma::{DmaDirection, DmaRegion};
 pub use iopad::*;
 pub use mci_host::*;
+pub use nb;
 
 pub trait Kernel {
     fn sleep(duration: Duration);
     fn mmap(virt_addr: NonNull<u8>) -> u64;
     fn flush(addr: NonNull<u8>, size: usize);
     fn invalidate(addr: core::ptr::NonNull<u8>, size: usize);
+    /// Monotonic time elapsed since a fixed point, e.g. system boot
+    fn now() -> Duration;
 }
 
 pub(crate) fn sleep(duration: Duration) {
@@ -61,6 +66,14 @@ pub(crate) fn invalidate(addr: core::ptr::NonNull<u8>, size: usize) {
     }
 }
 
+pub(crate) fn now() -> Duration {
+    extern "Rust" {
+        fn _phytium_mci_now() -> Duration;
+    }
+
+    unsafe { _phytium_mci_now() }
+}
+
 #[macro_export]
 macro_rules! set_impl {
     ($t: ty) => {
@@ -80,5 +93,9 @@ macro_rules! set_impl {
         fn _phytium_mci_invalidate(addr: core::ptr::NonNull<u8>, size: usize) {
             <$t as $crate::Kernel>::invalidate(addr, size)
         }
+        #[no_mangle]
+        fn _phytium_mci_now() -> core::time::Duration {
+            <$t as $crate::Kernel>::now()
+        }
     };
 }
@@ -1,4 +1,5 @@
 use bitflags::bitflags;
+use core::time::Duration;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum MCIId {
@@ -76,18 +77,25 @@ pub enum MCIClkSpeed {
     ClkSpeed100Mhz = 100_000_000,
 }
 
+impl MCIClkSpeed {
+    /// `None` if `hz` is not one of the tuned speeds
+    pub fn from_hz(hz: u32) -> Option<Self> {
+        match hz {
+            400_000 => Some(MCIClkSpeed::ClkSpeed400KHz),
+            25_000_000 => Some(MCIClkSpeed::ClkSpeed25Mhz),
+            26_000_000 => Some(MCIClkSpeed::ClkSpeed26Mhz),
+            50_000_000 => Some(MCIClkSpeed::ClkSpeed50Mhz),
+            52_000_000 => Some(MCIClkSpeed::ClkSpeed52Mhz),
+            66_000_000 => Some(MCIClkSpeed::ClkSpeed66Mhz),
+            100_000_000 => Some(MCIClkSpeed::ClkSpeed100Mhz),
+            _ => None,
+        }
+    }
+}
+
 impl From<u32> for MCIClkSpeed {
     fn from(value: u32) -> Self {
-        match value {
-            400_000 => MCIClkSpeed::ClkSpeed400KHz,
-            25_000_000 => MCIClkSpeed::ClkSpeed25Mhz,
-            26_000_000 => MCIClkSpeed::ClkSpeed26Mhz,
-            50_000_000 => MCIClkSpeed::ClkSpeed50Mhz,
-            52_000_000 => MCIClkSpeed::ClkSpeed52Mhz,
-            66_000_000 => MCIClkSpeed::ClkSpeed66Mhz,
-            100_000_000 => MCIClkSpeed::ClkSpeed100Mhz,
-            _ => panic!("Invalid clock speed"),
-        }
+        Self::from_hz(value).expect("Invalid clock speed")
     }
 }
 
@@ -141,8 +149,9 @@ pub const FSDIF_EMMC_DDR_REG_OFFSET: u32 = 0x10C; // the EMMC DDR reg
 pub const FSDIF_ENABLE_SHIFT_OFFSET: u32 = 0x110; // the enable phase shift reg
 pub const FSDIF_DATA_OFFSET: u32 = 0x200; // the data FIFO access
 
-pub const RETRIES_TIMEOUT: usize = 50000; /* timeout for retries */
-pub const COMMAND_TIMEOUT: u32 = 5000;
+pub const REG_TIMEOUT: Duration = Duration::from_millis(100); /* timeout for register polling */
+pub const CARD_BUSY_TIMEOUT: Duration = Duration::from_millis(600); /* timeout for card releasing DAT0 */
+pub const COMMAND_TIMEOUT: Duration = Duration::from_millis(100); /* timeout for command done, without data */
 pub const FSDIF_DELAY_US: u32 = 5;
 pub const MCI_MAX_FIFO_CNT: u32 = 0x800;
 
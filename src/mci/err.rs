@@ -1,6 +1,8 @@
+use super::regs::{MCIDMACStatus, MCIRawInts};
 use super::RegError;
+use super::MCI;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MCIError {
     Timeout,
     NotInit,
@@ -13,6 +15,82 @@ pub enum MCIError {
     Busy,
     DmaBufUnalign,
     InvalidTiming,
+    /// RE, 响应错误
+    ResponseErr,
+    /// RCRC, 响应 CRC 错误
+    ResponseCrc,
+    /// RTO, 响应超时
+    ResponseTimeout,
+    /// DCRC, 数据 CRC 错误
+    DataCrc,
+    /// DRTO, 数据读超时
+    DataReadTimeout,
+    /// HTO, FIFO 空/满时主机未及时搬运数据
+    DataStarvation,
+    /// FRUN, FIFO 下溢/上溢
+    FifoRun,
+    /// HLE, 控制器忙时写入命令寄存器
+    HardwareLocked,
+    /// SBE, 起始位错误
+    StartBit,
+    /// EBE, 读数据结束位错误或写数据无 CRC 状态
+    EndBit,
+    /// FBE, IDMAC 访问总线出错
+    DmaBusFatal,
+    /// DU, IDMAC 描述符不可用
+    DescUnavailable,
+}
+
+/* 同时出现多个错误时, 控制器和 IDMAC 错误优先, 其次是响应, 最后是数据 */
+const RAW_INTS_ERRORS: [(MCIRawInts, MCIError); 9] = [
+    (MCIRawInts::RTO_BIT, MCIError::ResponseTimeout),
+    (MCIRawInts::RCRC_BIT, MCIError::ResponseCrc),
+    (MCIRawInts::RE_BIT, MCIError::ResponseErr),
+    (MCIRawInts::SBE_BCI_BIT, MCIError::StartBit),
+    (MCIRawInts::EBE_BIT, MCIError::EndBit),
+    (MCIRawInts::DCRC_BIT, MCIError::DataCrc),
+    (MCIRawInts::DRTO_BIT, MCIError::DataReadTimeout),
+    (MCIRawInts::FRUN_BIT, MCIError::FifoRun),
+    (MCIRawInts::HTO_BIT, MCIError::DataStarvation),
+];
+
+impl MCIError {
+    /// Decode the error of command `cmd_index` from raw interrupt and IDMAC status,
+    /// `None` if no error bit is set
+    pub(crate) fn from_status(raw_ints: u32, dmac_status: u32, cmd_index: u32) -> Option<Self> {
+        let dmac_status = MCIDMACStatus::from_bits_truncate(dmac_status);
+        if dmac_status.contains(MCIDMACStatus::FBE) {
+            return Some(MCIError::DmaBusFatal);
+        }
+
+        let mut raw_ints = MCIRawInts::from_bits_truncate(raw_ints);
+        /* CMD11 切换电压时以 HTO 表示命令完成 */
+        if cmd_index == MCI::SWITCH_VOLTAGE {
+            raw_ints.remove(MCIRawInts::HTO_BIT);
+        }
+        if raw_ints.contains(MCIRawInts::HLE_BIT) {
+            return Some(MCIError::HardwareLocked);
+        }
+        if dmac_status.intersects(MCIDMACStatus::DU_BIT0 | MCIDMACStatus::DU_BIT1) {
+            return Some(MCIError::DescUnavailable);
+        }
+
+        RAW_INTS_ERRORS
+            .iter()
+            .find(|(bit, _)| raw_ints.contains(*bit))
+            .map(|(_, err)| *err)
+    }
+
+    /// 命令阶段的错误, 其余为数据阶段的错误
+    pub(crate) fn is_cmd_error(&self) -> bool {
+        matches!(
+            self,
+            MCIError::ResponseErr
+                | MCIError::ResponseCrc
+                | MCIError::ResponseTimeout
+                | MCIError::HardwareLocked
+        )
+    }
 }
 
 impl RegError for MCIError {
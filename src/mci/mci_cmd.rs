@@ -16,7 +16,7 @@ impl MCI {
 
         reg.retry_for(
             |reg: MCIStatus| !reg.contains(MCIStatus::DATA_BUSY),
-            Some(RETRIES_TIMEOUT),
+            Some(CARD_BUSY_TIMEOUT),
         )?;
         reg.write_reg(MCICmdArg::from_bits_truncate(arg));
 
@@ -27,7 +27,7 @@ impl MCI {
 
         reg.retry_for(
             |reg: MCICmd| !reg.contains(MCICmd::START),
-            Some(RETRIES_TIMEOUT),
+            Some(REG_TIMEOUT),
         )?;
 
         Ok(())
@@ -37,10 +37,7 @@ impl MCI {
         let reg = self.config.reg();
         unsafe { dsb() }; /* drain writebuffer */
         reg.write_reg(MCICmd::START | cmd);
-        reg.retry_for(
-            |reg| (MCICmd::START & reg).bits() == 0,
-            Some(RETRIES_TIMEOUT),
-        )?;
+        reg.retry_for(|reg| (MCICmd::START & reg).bits() == 0, Some(REG_TIMEOUT))?;
         Ok(())
     }
 
@@ -70,6 +67,10 @@ impl MCI {
             if flag.contains(MCICmdFlag::WRITE_DATA) {
                 raw_cmd |= MCICmd::DAT_WRITE;
             }
+            /* 数据传输结束后由控制器发送 CMD12 */
+            if flag.contains(MCICmdFlag::NEED_AUTO_STOP) {
+                raw_cmd |= MCICmd::SEND_AUTO_STOP;
+            }
         }
         /* 命令需要进行CRC校验 */
         if flag.contains(MCICmdFlag::NEED_RESP_CRC) {
@@ -167,7 +168,7 @@ impl MCI {
             false,
         );
         self.interrupt_mask_set(MCIIntrType::DmaIntr, MCIDMACIntEn::INTS_MASK.bits(), false);
-        info!("cmd send done ...");
+        debug!("cmd send done ...");
 
         self.prev_cmd = cmd_data.cmdidx();
 
@@ -1,7 +1,9 @@
+use core::time::Duration;
+
 use super::consts::*;
 use super::mci_data::MCIData;
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct MCICmdData {
     cmdidx: u32,
     cmdarg: u32,
@@ -88,4 +90,25 @@ impl MCICmdData {
     pub(crate) fn set_data(&mut self, data: Option<MCIData>) {
         self.data = data
     }
+
+    /// 不含数据缓冲区的副本, DMA 缓冲区只能有一个所有者
+    pub(crate) fn header(&self) -> MCICmdData {
+        MCICmdData {
+            cmdidx: self.cmdidx,
+            cmdarg: self.cmdarg,
+            response: self.response,
+            flag: self.flag,
+            data: self.data.as_ref().map(MCIData::header),
+            success: self.success,
+        }
+    }
+
+    /// 命令及数据阶段总超时
+    pub(crate) fn timeout(&self) -> Duration {
+        COMMAND_TIMEOUT
+            + self
+                .data
+                .as_ref()
+                .map_or(Duration::ZERO, |data| data.timeout())
+    }
 }
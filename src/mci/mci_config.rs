@@ -61,6 +61,18 @@ impl MCIConfig {
         }
     }
 
+    /// Timing for `clk_hz` and the card clock it achieves, board tuned table first,
+    /// otherwise computed from the input clock
+    pub fn timing_get(clk_hz: u32, non_removable: bool) -> Option<(MCITiming, u32)> {
+        if let Some(timing) = MCIClkSpeed::from_hz(clk_hz)
+            .and_then(|clock_freq| Self::get_tuning(clock_freq, non_removable))
+        {
+            let rate = timing.rate();
+            return Some((timing, rate));
+        }
+        MCITiming::compute(clk_hz)
+    }
+
     pub fn restart(addr: NonNull<u8>) -> Self {
         Self::new(addr)
     }
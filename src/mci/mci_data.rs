@@ -1,14 +1,19 @@
 use alloc::vec::Vec;
+use core::time::Duration;
 
-#[derive(Debug, Clone)]
+use super::mci_dma::MCIDmaSegment;
+use crate::osa::dma_buf::DmaBuf;
+
+#[derive(Debug)]
 pub(crate) struct MCIData {
-    // todo 使用智能指针涉及到大量的细微调整和代码修改，且十分容易造成潜在的非法的地址访问
-    // 暂时不考虑仿照源码使用指针来表示这里的buf
-    buf: Option<Vec<u32>>,
+    buf: Option<DmaBuf<u32>>, // 数据缓冲区, 由 DMA 内存提供者分配
+
     buf_dma: usize,
+    sg: Option<Vec<MCIDmaSegment>>, // 分散/聚集 DMA 段, 存在时不使用 buf
     blksz: u32,
     blkcnt: u32,
     datalen: u32,
+    timeout: Duration, // 数据阶段超时, 不含命令阶段
 }
 
 impl MCIData {
@@ -16,9 +21,24 @@ impl MCIData {
         MCIData {
             buf: None,
             buf_dma: 0,
+            sg: None,
             blksz: 0,
             blkcnt: 0,
             datalen: 0,
+            timeout: Duration::ZERO,
+        }
+    }
+
+    /// 不含数据缓冲区的副本
+    pub(crate) fn header(&self) -> MCIData {
+        MCIData {
+            buf: None,
+            buf_dma: self.buf_dma,
+            sg: self.sg.clone(),
+            blksz: self.blksz,
+            blkcnt: self.blkcnt,
+            datalen: self.datalen,
+            timeout: self.timeout,
         }
     }
 
@@ -46,19 +66,29 @@ impl MCIData {
         self.datalen = datalen
     }
 
-    pub(crate) fn buf(&self) -> Option<&Vec<u32>> {
+    pub(crate) fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub(crate) fn timeout_set(&mut self, timeout: Duration) {
+        self.timeout = timeout
+    }
+
+    pub(crate) fn buf(&self) -> Option<&DmaBuf<u32>> {
         self.buf.as_ref()
     }
 
-    pub(crate) fn buf_mut(&mut self) -> Option<&mut Vec<u32>> {
+    pub(crate) fn buf_mut(&mut self) -> Option<&mut DmaBuf<u32>> {
         self.buf.as_mut()
     }
 
-    pub(crate) fn buf_take(&mut self) -> Option<Vec<u32>> {
+    pub(crate) fn buf_take(&mut self) -> Option<DmaBuf<u32>> {
         self.buf.take()
     }
 
-    pub(crate) fn buf_set(&mut self, buf: Option<Vec<u32>>) {
+    /// 设置数据缓冲区, 总线地址取自缓冲区分配时解析的地址
+    pub(crate) fn buf_set(&mut self, buf: Option<DmaBuf<u32>>) {
+        self.buf_dma = buf.as_ref().map_or(0, |buf| buf.bus_addr() as usize);
         self.buf = buf
     }
 
@@ -66,7 +96,11 @@ impl MCIData {
         self.buf_dma
     }
 
-    pub(crate) fn buf_dma_set(&mut self, buf_dma: usize) {
-        self.buf_dma = buf_dma;
+    pub(crate) fn sg(&self) -> Option<&Vec<MCIDmaSegment>> {
+        self.sg.as_ref()
+    }
+
+    pub(crate) fn sg_set(&mut self, sg: Option<Vec<MCIDmaSegment>>) {
+        self.sg = sg
     }
 }
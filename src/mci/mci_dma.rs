@@ -1,9 +1,10 @@
 use core::ptr::NonNull;
 
 use alloc::vec::Vec;
+use bytemuck::{Pod, Zeroable};
 use log::*;
 
-use crate::flush;
+use crate::dma::{DmaDirection, DmaRegion};
 
 use super::consts::*;
 use super::err::*;
@@ -11,7 +12,8 @@ use super::mci_data::MCIData;
 use super::regs::*;
 use super::MCI;
 
-#[derive(Default)]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct FSdifIDmaDesc {
     pub attribute: u32,
     pub non1: u32,
@@ -23,6 +25,23 @@ pub struct FSdifIDmaDesc {
     pub desc_hi: u32,
 }
 
+// 全部为 u32 字段且无填充, 任意位模式都合法
+unsafe impl Zeroable for FSdifIDmaDesc {}
+unsafe impl Pod for FSdifIDmaDesc {}
+
+/// 分散/聚集 DMA 的一个物理连续段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MCIDmaSegment {
+    pub addr: usize, // 段的总线/物理地址, 需 4 字节对齐
+    pub len: u32,    // 段的字节数, 需为 4 的倍数
+}
+
+impl MCIDmaSegment {
+    pub fn new(addr: usize, len: u32) -> Self {
+        MCIDmaSegment { addr, len }
+    }
+}
+
 pub struct FSdifIDmaDescList {
     pub first_desc: *mut FSdifIDmaDesc,
     pub first_desc_dma: usize, // 第一个descriptor的物理地址
@@ -30,6 +49,9 @@ pub struct FSdifIDmaDescList {
     pub desc_trans_sz: u32, // 单个descriptor传输的字节数
 }
 
+/// 描述符内存由 SDIFDev 持有, 与 MCI 一同在锁内访问
+unsafe impl Send for FSdifIDmaDescList {}
+
 impl FSdifIDmaDescList {
     pub fn new() -> Self {
         FSdifIDmaDescList {
@@ -72,26 +94,44 @@ impl MCI {
     /// setup DMA descriptor list before do transcation
     pub(crate) fn setup_dma_descriptor(&mut self, data: &MCIData) -> MCIResult {
         let desc_list = &self.desc_list;
-        // 一个desc可以传输的块数
-        let desc_blocks = desc_list.desc_trans_sz / data.blksz();
-        let mut remain_blocks = data.blkcnt();
-        let mut buf_addr = data.buf_dma();
-        let mut trans_blocks: u32; // 本次循环被传输的块
-        let mut is_first;
-        let mut is_last;
-
-        let mut desc_num = 1u32;
         let data_len = data.blkcnt() * data.blksz();
-        // 计算需要多少desc来传输
-        if data_len > desc_list.desc_trans_sz {
-            desc_num = data_len / desc_list.desc_trans_sz;
-            desc_num += if data_len % desc_list.desc_trans_sz == 0 {
-                0
-            } else {
-                1
-            };
+
+        // 连续缓冲区视为只有一个段的分散/聚集请求
+        let single;
+        let segments = match data.sg() {
+            Some(sg) => sg.as_slice(),
+            None => {
+                single = [MCIDmaSegment::new(data.buf_dma(), data_len)];
+                &single[..]
+            }
+        };
+
+        // IDMAC 按 32 位访问总线, 每个段只需 4 字节对齐
+        for seg in segments {
+            if seg.addr % 4 != 0 || seg.len % 4 != 0 || seg.len == 0 {
+                error!(
+                    "DMA segment 0x{:x}, len 0x{:x} do not align to 4 bytes!",
+                    seg.addr, seg.len
+                );
+                return Err(MCIError::DmaBufUnalign);
+            }
         }
 
+        let seg_len = segments.iter().map(|seg| seg.len as u64).sum::<u64>();
+        if seg_len != data_len as u64 {
+            error!(
+                "DMA segments length 0x{:x} mismatch with data length 0x{:x}!",
+                seg_len, data_len
+            );
+            return Err(MCIError::ShortBuf);
+        }
+
+        // 计算需要多少desc来传输, 每个段单独按 desc_trans_sz 切分
+        let desc_num = segments
+            .iter()
+            .map(|seg| seg.len.div_ceil(desc_list.desc_trans_sz))
+            .sum::<u32>();
+
         if desc_num > desc_list.desc_num {
             error!(
                 "Transfer descriptor are not enough! desc need: {}, desc available: {}",
@@ -100,9 +140,10 @@ impl MCI {
             return Err(MCIError::ShortBuf);
         }
 
-        info!(
-            "DMA transfer 0x{:x} use {} desc, total {} available",
-            data.buf_dma(),
+        debug!(
+            "DMA transfer 0x{:x} in {} segment(s) use {} desc, total {} available",
+            segments[0].addr,
+            segments.len(),
             desc_num,
             desc_list.desc_num
         );
@@ -113,19 +154,25 @@ impl MCI {
             core::ptr::write_bytes(desc_list.first_desc as *mut u8, 0, total_size);
         }
 
-        for i in 0..desc_num {
-            trans_blocks = if remain_blocks <= desc_blocks {
-                remain_blocks
-            } else {
-                desc_blocks
-            };
+        let chunks = segments.iter().flat_map(|seg| {
+            (0..seg.len)
+                .step_by(desc_list.desc_trans_sz as usize)
+                .map(move |offset| {
+                    (
+                        seg.addr + offset as usize,
+                        (seg.len - offset).min(desc_list.desc_trans_sz),
+                    )
+                })
+        });
+
+        for (i, (buf_addr, trans_len)) in (0..desc_num).zip(chunks) {
             unsafe {
                 let cur_desc = self.desc_list.first_desc.add(i as usize);
                 let mut next_desc_addr = desc_list.first_desc_dma
                     + (i + 1) as usize * core::mem::size_of::<FSdifIDmaDesc>();
 
-                is_first = i == 0;
-                is_last = desc_num - 1 == i;
+                let is_first = i == 0;
+                let is_last = desc_num - 1 == i;
 
                 // set properity of descriptor entry
                 (*cur_desc).attribute = FSDIF_IDMAC_DES0_CH | FSDIF_IDMAC_DES0_OWN;
@@ -138,18 +185,9 @@ impl MCI {
 
                 // set data length in transfer
                 (*cur_desc).non1 = 0u32;
-                (*cur_desc).len = trans_blocks * data.blksz();
+                (*cur_desc).len = trans_len;
 
                 // set data buffer for transfer
-                if buf_addr % data.blksz() as usize != 0 {
-                    error!(
-                        "Data buffer 0x{:x} do not align to {}!",
-                        buf_addr,
-                        data.blksz()
-                    );
-                    return Err(MCIError::DmaBufUnalign);
-                }
-
                 if cfg!(target_arch = "aarch64") {
                     (*cur_desc).addr_hi = ((buf_addr >> 32) & 0xFFFF_FFFF) as u32;
                     (*cur_desc).addr_lo = (buf_addr & 0xFFFF_FFFF) as u32;
@@ -173,16 +211,15 @@ impl MCI {
                     (*cur_desc).desc_hi = 0;
                     (*cur_desc).desc_lo = (next_desc_addr & 0xFFFF_FFFF) as u32;
                 }
-
-                buf_addr += (*cur_desc).len as usize;
-                remain_blocks -= trans_blocks;
             }
         }
 
-        flush(
+        // 描述符由 IDMAC 读取, 按字节数写回整个描述符链
+        DmaRegion::new(
             NonNull::new(desc_list.first_desc).unwrap().cast(),
-            desc_num as _,
-        );
+            desc_num as usize * core::mem::size_of::<FSdifIDmaDesc>(),
+        )
+        .prepare(DmaDirection::ToDevice);
         self.dump_dma_descriptor(desc_num);
         debug!("set dma desc ok");
 
@@ -201,7 +238,7 @@ impl MCI {
         self.setup_dma_descriptor(&data)?;
 
         let data_len = data.blkcnt() * data.blksz();
-        info!(
+        debug!(
             "Descriptor@{:p}, trans bytes: {}, block size: {}",
             self.desc_list.first_desc,
             data_len,
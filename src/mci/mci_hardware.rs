@@ -1,4 +1,4 @@
-use super::MCI;
+use super::{MCICmdData, MCI};
 
 use super::consts::*;
 use super::err::*;
@@ -43,7 +43,7 @@ impl MCI {
         reg.write_reg(uhs_reg);
         reg.retry_for(
             |reg: MCIClkSts| reg.contains(MCIClkSts::READY),
-            Some(RETRIES_TIMEOUT),
+            Some(REG_TIMEOUT),
         )?;
         Ok(())
     }
@@ -93,10 +93,7 @@ impl MCI {
         let reg = self.config.reg();
 
         reg.modify_reg(|reg| reset_bits | reg);
-        if let Err(e) = reg.retry_for(
-            |reg: MCICtrl| !reg.contains(reset_bits),
-            Some(RETRIES_TIMEOUT),
-        ) {
+        if let Err(e) = reg.retry_for(|reg: MCICtrl| !reg.contains(reset_bits), Some(REG_TIMEOUT)) {
             error!("Reset failed, bits = 0x{:x}", reset_bits);
             return Err(e);
         }
@@ -111,7 +108,7 @@ impl MCI {
         if reset_bits.contains(MCICtrl::FIFO_RESET) {
             if let Err(e) = reg.retry_for(
                 |reg: MCIStatus| reg.contains(MCIStatus::FIFO_EMPTY),
-                Some(RETRIES_TIMEOUT),
+                Some(REG_TIMEOUT),
             ) {
                 error!("Fifo not empty!");
                 return Err(e);
@@ -198,12 +195,42 @@ impl MCI {
         reg.write_reg(self.dma_status_get());
     }
 
+    /// 解析当前传输的错误, 出错时清除中断和 IDMAC 状态
+    pub(crate) fn transfer_error_take(&self, cmd_data: &MCICmdData) -> Option<MCIError> {
+        let raw_ints = self.raw_status_get().bits();
+        /* PIO 模式下 IDMAC 状态没有意义 */
+        let dmac_status = if self.config.trans_mode() == MCITransMode::DMA {
+            self.dma_status_get().bits()
+        } else {
+            0
+        };
+
+        self.raw_ints_record(raw_ints);
+        let err = MCIError::from_status(raw_ints, dmac_status, cmd_data.cmdidx())?;
+        error!(
+            "CMD{} {:?}, raw ints: 0x{:x}, dmac status: 0x{:x}",
+            cmd_data.cmdidx(),
+            err,
+            raw_ints,
+            dmac_status
+        );
+        self.raw_status_clear();
+        self.dma_status_clear();
+        Some(err)
+    }
+
     pub(crate) fn check_if_card_exist(&self) -> bool {
         let reg = self.config.reg();
         !reg.read_reg::<MCICardDetect>()
             .contains(MCICardDetect::DETECTED)
     }
 
+    pub(crate) fn check_if_card_write_protected(&self) -> bool {
+        let reg = self.config.reg();
+        reg.read_reg::<MCICardWrtp>()
+            .contains(MCICardWrtp::WRITE_PROTECTED)
+    }
+
     pub(crate) fn check_if_card_busy(&self) -> bool {
         self.status_get().contains(MCIStatus::DATA_BUSY)
     }
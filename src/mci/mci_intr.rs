@@ -1,21 +1,25 @@
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use alloc::boxed::Box;
+use alloc::sync::{Arc, Weak};
 
 use log::debug;
 use log::error;
 use log::warn;
+use spin::Mutex;
 
-use crate::mci_sdif::sdif_device::SDIFDev;
 use crate::osa::consts::SDMMC_OSA_EVENT_CARD_INSERTED;
 use crate::osa::consts::SDMMC_OSA_EVENT_TRANSFER_CMD_FAIL;
 use crate::osa::consts::SDMMC_OSA_EVENT_TRANSFER_CMD_SUCCESS;
 use crate::osa::consts::SDMMC_OSA_EVENT_TRANSFER_DATA_FAIL;
 use crate::osa::consts::SDMMC_OSA_EVENT_TRANSFER_DATA_SUCCESS;
-use crate::osa::osa_event_set;
-use crate::sd::reg_base;
+use crate::osa::{osa_event_create, OsaEvent};
 
 use super::consts::*;
+use super::err::MCIError;
 use super::regs::*;
-use super::MCI;
+use super::{MCICmdData, MCI};
 
 impl MCI {
     /* Get SDIF controller interrupt mask */
@@ -47,129 +51,224 @@ impl MCI {
     }
 }
 
-/// Interrupt handler for SDIF instance
-pub fn fsdif_interrupt_handler() {
-    let reg = MCIReg::new(reg_base());
-
-    let events = reg.read_reg::<MCIRawInts>();
-    let dmac_events = reg.read_reg::<MCIDMACStatus>();
-
-    let event_mask = reg.read_reg::<MCIIntMask>();
-    let _dmac_evt_mask = reg.read_reg::<MCIDMACIntEn>();
-
-    reg.write_reg::<MCIRawInts>(events);
-    reg.write_reg::<MCIDMACStatus>(dmac_events);
-
-    // no interrupt status
-    if (events.bits() & MCIRawInts::ALL_BITS.bits() == 0)
-        && (dmac_events.bits() & MCIDMACStatus::ALL_BITS.bits() == 0)
-    {
-        return;
-    }
-
-    reg.write_reg::<IrqTempRegister>(IrqTempRegister::from_bits_truncate(0));
-
-    // no need to handle interrput
-    if (events.bits() == 0) && (dmac_events.bits() & 0x1FFF == 0) {
-        return;
-    }
-
-    // handle sdio irq
-    if (events.bits() & event_mask.bits()) & MCIRawInts::SDIO_BIT.bits() != 0 {
-        handle_sdio_interrupt();
-        return;
-    }
-
-    // handle card detect event
-    // todo 尚未实现卡检测相关事件
-    // if events.bits() & event_mask.bits() & MCIRawInts::CD_BIT.bits() != 0 &&
-    //     !self.config().non_removable()
-    // {
-    //     warn!("SD status changed here! status:[{}]", reg.read_reg::<MCICardDetect>().bits());
-    //     card_detected();
-    // }
-
-    // handle error state
-    if dmac_events.contains(MCIDMACStatus::DMAC_ERR_INTS_MASK)
-        || events.contains(MCIRawInts::CMD_ERR_INTS_MASK)
-    {
-        handle_error_occur(&reg, events.bits(), dmac_events.bits());
-        return;
-    }
-
-    // handle cmd && data done
-    if events.contains(MCIRawInts::DTO_BIT) && events.contains(MCIRawInts::CMD_BIT) {
-        handle_cmd_done();
-        handle_data_done(events.bits(), dmac_events.bits());
-    } else if events.contains(MCIRawInts::CMD_BIT)
-    // todo 这里无法得到MCI实例 暂时无法处理这种情况
-    // (events.contains(MCIRawInts::HTO_BIT) && self.cur_cmd_index() == MCI::SWITCH_VOLTAGE as isize)
-    {
-        handle_cmd_done();
-        return;
-    // } else if events.contains(MCIRawInts::CMD_BIT) {
-    //     // handle cmd done
-    //     handle_cmd_done();
-    } else if events.contains(MCIRawInts::DTO_BIT) {
-        // handle data done
-        handle_data_done(events.bits(), dmac_events.bits());
-        return;
-    }
+/// 中断处理与 MCI 实例共享的状态, 每个控制器一份
+pub(crate) struct MCIIrqState {
+    reg: MCIReg,
+    /// 本控制器的传输事件, 中断处理函数设置, 等待方清除
+    events: Box<dyn OsaEvent>,
+    /// 正在执行的命令索引
+    cmd_index: AtomicU32,
+    /// 正在执行的命令是否带数据
+    has_data: AtomicBool,
+    /// 出错时的原始中断状态和 IDMAC 状态, 由等待方取走解析
+    err_ints: AtomicU32,
+    err_dmac_status: AtomicU32,
+    /// 当前命令期间收到的全部原始中断状态
+    ints_seen: AtomicU32,
 }
 
-#[allow(dead_code)]
-fn handle_card_detected() {
-    osa_event_set(SDMMC_OSA_EVENT_CARD_INSERTED);
-}
+impl MCIIrqState {
+    const NO_CMD: u32 = u32::MAX;
 
-pub fn handle_cmd_done() {
-    osa_event_set(SDMMC_OSA_EVENT_TRANSFER_CMD_SUCCESS);
-}
+    pub(crate) fn new(addr: NonNull<u8>) -> Self {
+        MCIIrqState {
+            reg: MCIReg::new(addr),
+            events: osa_event_create(),
+            cmd_index: AtomicU32::new(Self::NO_CMD),
+            /* 未知时按带数据处理, 只有 DTO 才认为数据完成 */
+            has_data: AtomicBool::new(true),
+            err_ints: AtomicU32::new(0),
+            err_dmac_status: AtomicU32::new(0),
+            ints_seen: AtomicU32::new(0),
+        }
+    }
+
+    /// 发出命令前记录, 供中断上下文判断完成条件
+    pub(crate) fn cmd_start(&self, cmd_data: &MCICmdData) {
+        self.has_data
+            .store(cmd_data.get_data().is_some(), Ordering::Release);
+        self.err_ints.store(0, Ordering::Release);
+        self.err_dmac_status.store(0, Ordering::Release);
+        self.ints_seen.store(0, Ordering::Release);
+        self.cmd_index.store(cmd_data.cmdidx(), Ordering::Release);
+    }
+
+    pub(crate) fn events(&self) -> &dyn OsaEvent {
+        self.events.as_ref()
+    }
+
+    pub(crate) fn ints_seen(&self) -> u32 {
+        self.ints_seen.load(Ordering::Acquire)
+    }
+
+    /// 取走中断上下文记录的错误
+    pub(crate) fn error_take(&self) -> Option<MCIError> {
+        let raw_ints = self.err_ints.swap(0, Ordering::AcqRel);
+        let dmac_status = self.err_dmac_status.swap(0, Ordering::AcqRel);
+        MCIError::from_status(raw_ints, dmac_status, self.cmd_index())
+    }
 
-pub fn handle_data_done(status: u32, dmac_status: u32) {
-    let check_status = status
-        & (
-            MCIRawInts::DTO_BIT      // Data transfer over
-            | MCIRawInts::RCRC_BIT    // Response CRC error
-            | MCIRawInts::DCRC_BIT    // Data CRC error
-            | MCIRawInts::RE_BIT      // Response error
-            | MCIRawInts::DRTO_BIT    // Data read timeout
-            | MCIRawInts::EBE_BIT     // End-bit error
-            | MCIRawInts::SBE_BCI_BIT // Start-bit error
-            | MCIRawInts::RTO_BIT
-            // Response timeout
-        )
-            .bits();
-    let check_dmac = dmac_status & (MCIDMACIntEn::AIS | MCIDMACIntEn::DU).bits();
-
-    // todo 这里无法得到MCI实例 暂时无法处理这种情况
-    // if !dev.whether_transfer_data() {
-    //     osa_event_set(SDMMC_OSA_EVENT_TRANSFER_DATA_SUCCESS);
-    // } else if check_status | check_dmac != 0 {
-    //     if check_status & MCIRawInts::DTO_BIT.bits() != 0 {
-    //         osa_event_set(SDMMC_OSA_EVENT_TRANSFER_DATA_SUCCESS);
-    //     } else {
-    //         error!("transfer data error: 0x{:x}, dmac status: 0x{:x}", check_status, check_dmac);
-    //     }
-    // }
-
-    if check_status | check_dmac != 0 {
-        if check_status & MCIRawInts::DTO_BIT.bits() != 0 {
-            osa_event_set(SDMMC_OSA_EVENT_TRANSFER_DATA_SUCCESS);
+    fn cmd_index(&self) -> u32 {
+        self.cmd_index.load(Ordering::Acquire)
+    }
+
+    fn has_data(&self) -> bool {
+        self.has_data.load(Ordering::Acquire)
+    }
+
+    fn handle(&self) {
+        let reg = &self.reg;
+
+        let events = reg.read_reg::<MCIRawInts>();
+        let dmac_events = reg.read_reg::<MCIDMACStatus>();
+
+        let event_mask = reg.read_reg::<MCIIntMask>();
+        let _dmac_evt_mask = reg.read_reg::<MCIDMACIntEn>();
+
+        reg.write_reg::<MCIRawInts>(events);
+        reg.write_reg::<MCIDMACStatus>(dmac_events);
+        self.ints_seen.fetch_or(events.bits(), Ordering::AcqRel);
+
+        // no interrupt status
+        if (events.bits() & MCIRawInts::ALL_BITS.bits() == 0)
+            && (dmac_events.bits() & MCIDMACStatus::ALL_BITS.bits() == 0)
+        {
+            return;
+        }
+
+        reg.write_reg::<IrqTempRegister>(IrqTempRegister::from_bits_truncate(0));
+
+        // no need to handle interrput
+        if (events.bits() == 0) && (dmac_events.bits() & 0x1FFF == 0) {
+            return;
         }
+
+        // handle sdio irq
+        if (events.bits() & event_mask.bits()) & MCIRawInts::SDIO_BIT.bits() != 0 {
+            handle_sdio_interrupt();
+            return;
+        }
+
+        // handle card detect event
+        // todo 尚未实现卡检测相关事件
+        // if events.bits() & event_mask.bits() & MCIRawInts::CD_BIT.bits() != 0 &&
+        //     !self.config().non_removable()
+        // {
+        //     warn!("SD status changed here! status:[{}]", reg.read_reg::<MCICardDetect>().bits());
+        //     card_detected();
+        // }
+
+        // handle error state
+        if let Some(err) =
+            MCIError::from_status(events.bits(), dmac_events.bits(), self.cmd_index())
+        {
+            self.handle_error_occur(err, events.bits(), dmac_events.bits());
+            return;
+        }
+
+        // handle cmd && data done
+        if events.contains(MCIRawInts::DTO_BIT) && events.contains(MCIRawInts::CMD_BIT) {
+            self.handle_cmd_done();
+            self.handle_data_done(events.bits(), dmac_events.bits());
+        } else if events.contains(MCIRawInts::CMD_BIT)
+            || (events.contains(MCIRawInts::HTO_BIT) && self.cmd_index() == MCI::SWITCH_VOLTAGE)
+        {
+            /* CMD11 切换电压时以 HTO 表示命令完成 */
+            self.handle_cmd_done();
+        } else if events.contains(MCIRawInts::DTO_BIT) {
+            // handle data done
+            self.handle_data_done(events.bits(), dmac_events.bits());
+        }
+    }
+
+    /// 先记录错误状态再通知等待方
+    fn handle_error_occur(&self, err: MCIError, status: u32, dmac_status: u32) {
+        self.err_ints.fetch_or(status, Ordering::AcqRel);
+        self.err_dmac_status.fetch_or(dmac_status, Ordering::AcqRel);
+
+        if err.is_cmd_error() {
+            self.events.set(SDMMC_OSA_EVENT_TRANSFER_CMD_FAIL);
+        } else {
+            self.events.set(SDMMC_OSA_EVENT_TRANSFER_DATA_FAIL);
+        }
+    }
+
+    #[allow(dead_code)]
+    fn handle_card_detected(&self) {
+        self.events.set(SDMMC_OSA_EVENT_CARD_INSERTED);
+    }
+
+    fn handle_cmd_done(&self) {
+        self.events.set(SDMMC_OSA_EVENT_TRANSFER_CMD_SUCCESS);
+    }
+
+    fn handle_data_done(&self, status: u32, dmac_status: u32) {
+        if !self.has_data() {
+            self.events.set(SDMMC_OSA_EVENT_TRANSFER_DATA_SUCCESS);
+            return;
+        }
+
+        let check_status = status
+            & (
+                MCIRawInts::DTO_BIT      // Data transfer over
+                | MCIRawInts::RCRC_BIT    // Response CRC error
+                | MCIRawInts::DCRC_BIT    // Data CRC error
+                | MCIRawInts::RE_BIT      // Response error
+                | MCIRawInts::DRTO_BIT    // Data read timeout
+                | MCIRawInts::EBE_BIT     // End-bit error
+                | MCIRawInts::SBE_BCI_BIT // Start-bit error
+                | MCIRawInts::RTO_BIT
+                // Response timeout
+            )
+                .bits();
+        let check_dmac = dmac_status & (MCIDMACIntEn::AIS | MCIDMACIntEn::DU).bits();
+
+        if check_status | check_dmac != 0 {
+            if check_status & MCIRawInts::DTO_BIT.bits() != 0 {
+                self.events.set(SDMMC_OSA_EVENT_TRANSFER_DATA_SUCCESS);
+            } else {
+                error!(
+                    "transfer data error: 0x{:x}, dmac status: 0x{:x}",
+                    check_status, check_dmac
+                );
+            }
+        }
+    }
+}
+
+/// SDIF 实例的中断处理句柄, 可以交给其他核上的中断注册代码
+#[derive(Clone)]
+pub struct MCIIrqHandler(Arc<MCIIrqState>);
+
+impl MCIIrqHandler {
+    pub(crate) fn new(state: Arc<MCIIrqState>) -> Self {
+        MCIIrqHandler(state)
+    }
+
+    /// Interrupt handler for this SDIF instance
+    pub fn handle(&self) {
+        self.0.handle();
     }
 }
 
-fn handle_error_occur(_reg: &MCIReg, status: u32, dmac_status: u32) {
-    if status & MCIRawInts::RE_BIT.bits() != 0 || status & MCIRawInts::RTO_BIT.bits() != 0 {
-        osa_event_set(SDMMC_OSA_EVENT_TRANSFER_CMD_FAIL);
+/// 最近创建的 SDIF 实例, 仅供 [`fsdif_interrupt_handler`] 使用
+static LEGACY_IRQ_STATE: Mutex<Option<Weak<MCIIrqState>>> = Mutex::new(None);
+
+impl MCIIrqState {
+    pub(crate) fn legacy_register(state: &Arc<Self>) {
+        *LEGACY_IRQ_STATE.lock() = Some(Arc::downgrade(state));
     }
+}
 
-    if dmac_status & MCIDMACIntEn::DU.bits() != 0
-        || status & MCIRawInts::DCRC_BIT.bits() != 0
-        || status & MCIRawInts::RCRC_BIT.bits() != 0
-    {
-        osa_event_set(SDMMC_OSA_EVENT_TRANSFER_DATA_FAIL);
+/// Interrupt handler for the most recently created SDIF instance
+#[deprecated(
+    note = "each SDIF instance has its own event group, register `SdCard::irq_handler` instead"
+)]
+pub fn fsdif_interrupt_handler() {
+    let state = LEGACY_IRQ_STATE.lock().as_ref().and_then(Weak::upgrade);
+    match state {
+        Some(state) => state.handle(),
+        None => debug!("no SDIF instance for the interrupt"),
     }
 }
 
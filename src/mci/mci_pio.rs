@@ -8,7 +8,7 @@ use log::*;
 impl MCI {
     pub(crate) fn pio_write_data(&self, data: &MCIData) -> MCIResult {
         let reg = self.config.reg();
-        let wr_times: usize = (data.datalen() / 4) as usize; /* u8 --> u32 */
+        let wr_times: usize = data.datalen().div_ceil(4) as usize; /* u8 --> u32 */
         let buf = if let Some(buf) = data.buf() {
             buf
         } else {
@@ -26,15 +26,13 @@ impl MCI {
     pub(crate) fn pio_read_data(&self, data: &mut MCIData) -> MCIResult {
         let reg = self.config.reg();
         let datalen = data.datalen();
-        let rd_times = (datalen / 4) as usize; /* u8 --> u32 */
+        let rd_times = datalen.div_ceil(4) as usize; /* u8 --> u32 */
         let buf = if let Some(buf) = data.buf_mut() {
             buf
         } else {
             return Err(MCIError::NotInit);
         };
 
-        buf.clear();
-
         if datalen > MCI_MAX_FIFO_CNT {
             error!(
                 "Fifo do not support writing more than 0x{:x}.",
@@ -42,8 +40,11 @@ impl MCI {
             );
             return Err(MCIError::NotSupport);
         }
-        for _i in 0..rd_times {
-            buf.push(reg.read_reg::<MCIDataReg>().bits());
+        if buf.len() < rd_times {
+            return Err(MCIError::ShortBuf);
+        }
+        for word in buf[..rd_times].iter_mut() {
+            *word = reg.read_reg::<MCIDataReg>().bits();
         }
         Ok(())
     }
@@ -0,0 +1,187 @@
+//! 控制器寄存器快照, 用于错误和跟踪记录中比较传输前后的状态
+use alloc::vec::Vec;
+use core::fmt;
+
+use bitflags::Flags;
+
+use super::regs::*;
+use super::MCI;
+
+/// 主要寄存器的原始值, 由 [`MCI::register_snapshot`] 采集
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MCIRegisterSnapshot {
+    pub ctrl: u32,
+    pub status: u32,
+    pub raw_ints: u32,
+    pub int_mask: u32,
+    pub clk_div: u32,
+    pub clk_src: u32,
+    pub uhs_reg: u32,
+    pub fifo_th: u32,
+    pub dmac_status: u32,
+    pub cur_desc_addr: u64,
+    pub cur_buf_addr: u64,
+    /// 本次传输设置的字节数
+    pub byte_cnt: u32,
+    /// 已传给卡的字节数
+    pub card_cnt: u32,
+    /// 已经过 FIFO 的字节数
+    pub fifo_cnt: u32,
+}
+
+/// 两次快照之间变化的寄存器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MCIRegisterChange {
+    pub name: &'static str,
+    pub old: u64,
+    pub new: u64,
+}
+
+impl fmt::Display for MCIRegisterChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: 0x{:x} -> 0x{:x} (changed 0x{:x})",
+            self.name,
+            self.old,
+            self.new,
+            self.old ^ self.new
+        )
+    }
+}
+
+impl MCIRegisterSnapshot {
+    fn fields(&self) -> [(&'static str, u64); 14] {
+        [
+            ("ctrl", self.ctrl as u64),
+            ("status", self.status as u64),
+            ("raw_ints", self.raw_ints as u64),
+            ("int_mask", self.int_mask as u64),
+            ("clk_div", self.clk_div as u64),
+            ("clk_src", self.clk_src as u64),
+            ("uhs_reg", self.uhs_reg as u64),
+            ("fifo_th", self.fifo_th as u64),
+            ("dmac_status", self.dmac_status as u64),
+            ("cur_desc_addr", self.cur_desc_addr),
+            ("cur_buf_addr", self.cur_buf_addr),
+            ("byte_cnt", self.byte_cnt as u64),
+            ("card_cnt", self.card_cnt as u64),
+            ("fifo_cnt", self.fifo_cnt as u64),
+        ]
+    }
+
+    /// Registers whose value differs from `earlier`
+    pub fn diff(&self, earlier: &MCIRegisterSnapshot) -> Vec<MCIRegisterChange> {
+        earlier
+            .fields()
+            .into_iter()
+            .zip(self.fields())
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((name, old), (_, new))| MCIRegisterChange { name, old, new })
+            .collect()
+    }
+}
+
+/// 按寄存器定义打印置位的单比特标志
+fn flag_names<F: Flags<Bits = u32>>(f: &mut fmt::Formatter<'_>, bits: u32) -> fmt::Result {
+    let mut sep = "";
+    for (name, flag) in F::from_bits_truncate(bits).iter_names() {
+        if flag.bits().count_ones() == 1 {
+            write!(f, "{}{}", sep, name)?;
+            sep = "|";
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for MCIRegisterSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ctrl 0x{:08x} [", self.ctrl)?;
+        flag_names::<MCICtrl>(f, self.ctrl)?;
+        writeln!(f, "]")?;
+
+        write!(f, "status 0x{:08x} [", self.status)?;
+        flag_names::<MCIStatus>(f, self.status & 0xc000_070f)?;
+        writeln!(
+            f,
+            "] cmd_fsm {} resp_index {} fifo_cnt {}",
+            (self.status >> 4) & 0xf,
+            (self.status >> 11) & 0x3f,
+            (self.status >> 17) & 0x1fff
+        )?;
+
+        write!(f, "raw_ints 0x{:08x} [", self.raw_ints)?;
+        flag_names::<MCIRawInts>(f, self.raw_ints)?;
+        write!(f, "] int_mask 0x{:08x} [", self.int_mask)?;
+        flag_names::<MCIIntMask>(f, self.int_mask)?;
+        writeln!(f, "]")?;
+
+        writeln!(
+            f,
+            "clk_div 0x{:08x} divider {} drv {} sample {}",
+            self.clk_div,
+            self.clk_div & 0xff,
+            (self.clk_div >> 8) & 0xff,
+            (self.clk_div >> 16) & 0xff
+        )?;
+        writeln!(
+            f,
+            "clk_src 0x{:08x} uhs_div {} sample {} drv {} ext_clk {}",
+            self.clk_src,
+            (self.clk_src >> 8) & 0x7f,
+            (self.clk_src >> 16) & 0x7f,
+            (self.clk_src >> 24) & 0x7f,
+            self.clk_src & MCIClkSrc::UHS_EXT_CLK_ENA.bits() != 0
+        )?;
+        write!(f, "uhs_reg 0x{:08x} [", self.uhs_reg)?;
+        flag_names::<MCIUhsReg>(f, self.uhs_reg)?;
+        writeln!(
+            f,
+            "] fifo_th 0x{:08x} burst {} rx_wmark {} tx_wmark {}",
+            self.fifo_th,
+            (self.fifo_th >> 28) & 0x7,
+            (self.fifo_th >> 16) & 0xfff,
+            self.fifo_th & 0xfff
+        )?;
+
+        write!(f, "dmac_status 0x{:08x} [", self.dmac_status)?;
+        flag_names::<MCIDMACStatus>(f, self.dmac_status & 0x327)?;
+        writeln!(
+            f,
+            "] du {} eb {} fsm {}",
+            (self.dmac_status >> 3) & 0x3,
+            (self.dmac_status >> 10) & 0x7,
+            (self.dmac_status >> 13) & 0xf
+        )?;
+        write!(
+            f,
+            "cur_desc 0x{:x} cur_buf 0x{:x} bytes {} card {} fifo {}",
+            self.cur_desc_addr, self.cur_buf_addr, self.byte_cnt, self.card_cnt, self.fifo_cnt
+        )
+    }
+}
+
+impl MCI {
+    /// 采集主要寄存器, 不修改任何状态
+    pub fn register_snapshot(&self) -> MCIRegisterSnapshot {
+        let reg = self.config.reg();
+        MCIRegisterSnapshot {
+            ctrl: reg.read_reg::<MCICtrl>().bits(),
+            status: reg.read_reg::<MCIStatus>().bits(),
+            raw_ints: reg.read_reg::<MCIRawInts>().bits(),
+            int_mask: reg.read_reg::<MCIIntMask>().bits(),
+            clk_div: reg.read_reg::<MCIClkDiv>().bits(),
+            clk_src: reg.read_reg::<MCIClkSrc>().bits(),
+            uhs_reg: reg.read_reg::<MCIUhsReg>().bits(),
+            fifo_th: reg.read_reg::<MCIFifoTh>().bits(),
+            dmac_status: reg.read_reg::<MCIDMACStatus>().bits(),
+            cur_desc_addr: (reg.read_reg::<MCIDescAddrH>().bits() as u64) << 32
+                | reg.read_reg::<MCICurDescAddrL>().bits() as u64,
+            cur_buf_addr: (reg.read_reg::<MCIBufAddrH>().bits() as u64) << 32
+                | reg.read_reg::<MCICurBufAddrL>().bits() as u64,
+            byte_cnt: reg.read_reg::<MCIBytCnt>().bits(),
+            card_cnt: reg.read_reg::<MCITranCardCnt>().bits(),
+            fifo_cnt: reg.read_reg::<MCITranFifoCnt>().bits(),
+        }
+    }
+}
@@ -4,6 +4,15 @@ use crate::iopad::IoPad;
 use crate::regs::BitsOps;
 
 use super::consts::*;
+use super::regs::MCIClkSrc;
+
+/* 卡时钟 = 1.2GHz 输入时钟 / (UHS 分频 + 1) / (2 * CLK_DIVIDER)
+ * UHS 分频在 MCIClkSrc[14:8], CLK_DIVIDER 在 MCIClkDiv[7:0] */
+const MCI_INPUT_CLK_HZ: u64 = 1_200_000_000;
+const MCI_UHS_CLK_DIV_MIN: u32 = 1;
+const MCI_UHS_CLK_DIV_MAX: u32 = 0x7f;
+const MCI_CLK_DIVIDER_MIN: u32 = 2;
+const MCI_CLK_DIVIDER_MAX: u32 = 0xff;
 
 pub struct MCITiming {
     use_hold: bool,
@@ -30,6 +39,91 @@ enum MCIPadDelay {
     Set,
     Unset,
     None,
+    /// 板级时序表指定的管脚和 (粗调, 细调) 延时, `None` 时关闭延时
+    Custom(MCICclkPad, Option<(FioPadDelay, FioPadDelay)>),
+}
+
+/// CCLK 输出管脚, 板级时序表的延时写入该管脚的延时寄存器,
+/// 其他管脚需要先在 iopad 的寄存器定义中添加
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MCICclkPad {
+    /// AJ49, 内置时序中 SDIF0 使用
+    Aj49,
+    /// J53, 内置时序中 SDIF1 使用
+    J53,
+}
+
+impl MCICclkPad {
+    /// 内置时序使用的 CCLK 管脚
+    pub const fn default_for(mci_id: MCIId) -> Self {
+        match mci_id {
+            MCIId::MCI0 => MCICclkPad::Aj49,
+            MCIId::MCI1 => MCICclkPad::J53,
+        }
+    }
+}
+
+/// 某一卡时钟频率下的板级时序
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MCIBoardTiming {
+    /// 卡时钟频率
+    pub clk_hz: u32,
+    /// 仅用于 DDR 模式, 同一频率可以分别配置 SDR 和 DDR 时序, 没有内置 DDR 时序,
+    /// 表中没有 50MHz 的 DDR 时序时不会启用 DDR50
+    pub ddr: bool,
+    /// 命令使用 HOLD 寄存器
+    pub use_hold: bool,
+    /// MCIClkDiv 寄存器值
+    pub clk_div: u32,
+    /// MCIClkSrc 寄存器值
+    pub clk_src: u32,
+    /// MCIEnableShift 寄存器值
+    pub shift: u32,
+    /// CCLK 输出延时 (粗调, 细调), 写入 [`MCITimingProfile`] 指定的管脚, `None` 时关闭延时
+    pub pad_delay: Option<(FioPadDelay, FioPadDelay)>,
+}
+
+/// 板级时序表, 按控制器实例分别配置, 表中没有的 SDR 频率仍使用内置时序
+#[derive(Debug, Clone, Copy)]
+pub struct MCITimingProfile {
+    pub mci0: &'static [MCIBoardTiming],
+    pub mci1: &'static [MCIBoardTiming],
+    /// SDIF0 的 CCLK 管脚, 通常为 [`MCICclkPad::default_for`]
+    pub mci0_pad: MCICclkPad,
+    /// SDIF1 的 CCLK 管脚
+    pub mci1_pad: MCICclkPad,
+}
+
+impl MCITimingProfile {
+    /// Timings of controller `mci_id`
+    pub fn timings(&self, mci_id: MCIId) -> &'static [MCIBoardTiming] {
+        match mci_id {
+            MCIId::MCI0 => self.mci0,
+            MCIId::MCI1 => self.mci1,
+        }
+    }
+
+    /// CCLK pad of controller `mci_id`
+    pub fn pad(&self, mci_id: MCIId) -> MCICclkPad {
+        match mci_id {
+            MCIId::MCI0 => self.mci0_pad,
+            MCIId::MCI1 => self.mci1_pad,
+        }
+    }
+
+    pub(crate) fn lookup(&self, mci_id: MCIId, clk_hz: u32, ddr: bool) -> Option<MCITiming> {
+        let pad = self.pad(mci_id);
+        self.timings(mci_id)
+            .iter()
+            .find(|timing| timing.clk_hz == clk_hz && timing.ddr == ddr)
+            .map(|timing| MCITiming {
+                use_hold: timing.use_hold,
+                clk_div: timing.clk_div,
+                clk_src: timing.clk_src,
+                shift: timing.shift,
+                pad_delay: MCIPadDelay::Custom(pad, timing.pad_delay),
+            })
+    }
 }
 
 impl MCITiming {
@@ -38,9 +132,51 @@ impl MCITiming {
             MCIPadDelay::Set => set_pad_delay(iopad, mci_id),
             MCIPadDelay::Unset => unset_pad_delay(iopad, mci_id),
             MCIPadDelay::None => {}
+            MCIPadDelay::Custom(pad, Some((coarse, fine))) => {
+                pad_delay_apply(iopad, pad, coarse, fine, true)
+            }
+            MCIPadDelay::Custom(pad, None) => pad_delay_apply(
+                iopad,
+                pad,
+                FioPadDelay::DelayNone,
+                FioPadDelay::DelayNone,
+                false,
+            ),
         }
     }
 
+    /// 由输入时钟计算分频参数, 返回时序和不超过 `clk_hz` 的最接近频率
+    pub(crate) fn compute(clk_hz: u32) -> Option<(MCITiming, u32)> {
+        let (rate, uhs_div, divider) = divider_search(clk_hz)?;
+
+        /* CLK_DRV 和 CLK_SAMPLE 必须小于 CLK_DIVIDER, 采样点在输出后半个周期 */
+        let (drv, sample) = if divider > 2 {
+            (divider / 2, divider / 2 + 1)
+        } else {
+            (0, 1)
+        };
+
+        let timing = MCITiming {
+            use_hold: rate <= 50_000_000,
+            clk_div: (sample << 16) | (drv << 8) | divider,
+            clk_src: (MCIClkSrc::uhs_reg(0, 0, uhs_div) | MCIClkSrc::UHS_EXT_CLK_ENA).bits(),
+            shift: 0x0,
+            pad_delay: if rate > 25_000_000 {
+                MCIPadDelay::Set
+            } else {
+                MCIPadDelay::Unset
+            },
+        };
+        Some((timing, rate))
+    }
+
+    /// 按 MCIClkSrc 和 MCIClkDiv 寄存器值算出的卡时钟频率
+    pub(crate) const fn rate(&self) -> u32 {
+        let uhs_div = (self.clk_src >> 8) & MCI_UHS_CLK_DIV_MAX;
+        let divider = self.clk_div & MCI_CLK_DIVIDER_MAX;
+        clk_rate(uhs_div, divider)
+    }
+
     pub(crate) fn clk_src(&self) -> u32 {
         self.clk_src
     }
@@ -58,6 +194,47 @@ impl MCITiming {
     }
 }
 
+/// 分频组合对应的卡时钟频率, CLK_DIVIDER 为 0 时旁路分频
+const fn clk_rate(uhs_div: u32, divider: u32) -> u32 {
+    let ciu_hz = MCI_INPUT_CLK_HZ / (uhs_div as u64 + 1);
+    if divider == 0 {
+        ciu_hz as u32
+    } else {
+        (ciu_hz / (2 * divider as u64)) as u32
+    }
+}
+
+/// 搜索不超过 `clk_hz` 的最高卡时钟, 返回 (频率, UHS 分频, CLK_DIVIDER)
+const fn divider_search(clk_hz: u32) -> Option<(u32, u32, u32)> {
+    if clk_hz == 0 {
+        return None;
+    }
+
+    /* 频率相同时取 UHS 分频较小的组合, CIU 时钟越快相位调节越精细 */
+    let mut best: Option<(u32, u32, u32)> = None;
+    let mut uhs_div = MCI_UHS_CLK_DIV_MIN;
+    while uhs_div <= MCI_UHS_CLK_DIV_MAX {
+        let ciu_hz = MCI_INPUT_CLK_HZ / (uhs_div as u64 + 1);
+        let mut divider = ciu_hz.div_ceil(2 * clk_hz as u64);
+        if divider < MCI_CLK_DIVIDER_MIN as u64 {
+            divider = MCI_CLK_DIVIDER_MIN as u64;
+        }
+
+        if divider <= MCI_CLK_DIVIDER_MAX as u64 {
+            let rate = clk_rate(uhs_div, divider as u32);
+            let better = match best {
+                Some((best_rate, _, _)) => rate > best_rate,
+                None => true,
+            };
+            if better {
+                best = Some((rate, uhs_div, divider as u32));
+            }
+        }
+        uhs_div += 1;
+    }
+    best
+}
+
 pub const MMC_SD_400K_HZ: MCITiming = MCITiming {
     use_hold: true,
     clk_div: 0x7e7dfa,
@@ -122,10 +299,6 @@ pub const MMC_100MHZ: MCITiming = MCITiming {
     pad_delay: MCIPadDelay::Set,
 };
 
-/* 管脚相关定义 */
-type Fsdif0SdCclkOutDelay = Aj49Reg1;
-type Fsdif1SdCclkOutDelay = J53Reg1;
-
 fn apply_delay_settings<T: XReg1 + BitsOps>(
     iopad: &mut IoPad,
     coarse_delay: FioPadDelay,
@@ -147,36 +320,37 @@ fn apply_delay_settings<T: XReg1 + BitsOps>(
     iopad.delay_enable_set::<T>(FioPadDelayDir::OutputDelay, enable);
 }
 
-pub fn set_pad_delay(iopad: &mut IoPad, mci_id: MCIId) {
-    match mci_id {
-        MCIId::MCI0 => apply_delay_settings::<Fsdif0SdCclkOutDelay>(
-            iopad,
-            FioPadDelay::Delay1,
-            FioPadDelay::Delay7,
-            true,
-        ),
-        MCIId::MCI1 => apply_delay_settings::<Fsdif1SdCclkOutDelay>(
-            iopad,
-            FioPadDelay::Delay1,
-            FioPadDelay::Delay7,
-            true,
-        ),
+fn pad_delay_apply(
+    iopad: &mut IoPad,
+    pad: MCICclkPad,
+    coarse_delay: FioPadDelay,
+    fine_delay: FioPadDelay,
+    enable: bool,
+) {
+    match pad {
+        MCICclkPad::Aj49 => {
+            apply_delay_settings::<Aj49Reg1>(iopad, coarse_delay, fine_delay, enable)
+        }
+        MCICclkPad::J53 => apply_delay_settings::<J53Reg1>(iopad, coarse_delay, fine_delay, enable),
     }
 }
 
+pub fn set_pad_delay(iopad: &mut IoPad, mci_id: MCIId) {
+    pad_delay_apply(
+        iopad,
+        MCICclkPad::default_for(mci_id),
+        FioPadDelay::Delay1,
+        FioPadDelay::Delay7,
+        true,
+    );
+}
+
 pub fn unset_pad_delay(iopad: &mut IoPad, mci_id: MCIId) {
-    match mci_id {
-        MCIId::MCI0 => apply_delay_settings::<Fsdif0SdCclkOutDelay>(
-            iopad,
-            FioPadDelay::DelayNone,
-            FioPadDelay::DelayNone,
-            false,
-        ),
-        MCIId::MCI1 => apply_delay_settings::<Fsdif1SdCclkOutDelay>(
-            iopad,
-            FioPadDelay::DelayNone,
-            FioPadDelay::DelayNone,
-            false,
-        ),
-    }
+    pad_delay_apply(
+        iopad,
+        MCICclkPad::default_for(mci_id),
+        FioPadDelay::DelayNone,
+        FioPadDelay::DelayNone,
+        false,
+    );
 }
@@ -12,6 +12,7 @@ pub mod mci_dma;
 mod mci_hardware;
 mod mci_intr;
 mod mci_pio;
+mod mci_snapshot;
 mod mci_timing;
 
 use alloc::vec::Vec;
@@ -21,14 +22,21 @@ use log::*;
 use mci_dma::{FSdifIDmaDesc, FSdifIDmaDescList};
 use regs::*;
 
+pub(crate) use err::MCIError;
 pub use mci_cmddata::*;
 pub use mci_config::*;
+#[allow(deprecated)]
 pub use mci_intr::fsdif_interrupt_handler;
+pub use mci_intr::MCIIrqHandler;
+pub(crate) use mci_intr::MCIIrqState;
+pub use mci_snapshot::{MCIRegisterChange, MCIRegisterSnapshot};
 pub use mci_timing::*;
 
 use crate::flush;
 use crate::mmap;
-use crate::{aarch::dsb, osa::pool_buffer::PoolBuffer, regs::*, sleep, IoPad};
+use crate::tools::Deadline;
+use crate::{aarch::dsb, osa::dma_buf::DmaBuf, regs::*, sleep, IoPad};
+use core::sync::atomic::{AtomicU32, Ordering};
 use core::{ptr::NonNull, time::Duration};
 
 pub struct MCI {
@@ -39,6 +47,10 @@ pub struct MCI {
     cur_cmd: Option<MCICmdData>,
     curr_timing: MCITiming,
     io_pad: Option<IoPad>,
+    /// 板级时序表, 优先于内置时序
+    timing_profile: Option<MCITimingProfile>,
+    /// 当前传输期间轮询到的原始中断状态
+    raw_ints_seen: AtomicU32,
 }
 
 impl MCI {
@@ -57,6 +69,8 @@ impl MCI {
             curr_timing: MCITiming::new(),
             cur_cmd: None,
             io_pad: None,
+            timing_profile: None,
+            raw_ints_seen: AtomicU32::new(0),
             desc_list: FSdifIDmaDescList::new(),
         }
     }
@@ -69,6 +83,8 @@ impl MCI {
             curr_timing: MCITiming::new(),
             cur_cmd: None,
             io_pad: None,
+            timing_profile: None,
+            raw_ints_seen: AtomicU32::new(0),
             desc_list: FSdifIDmaDescList::new(),
         }
     }
@@ -106,9 +122,34 @@ impl MCI {
         self.io_pad.take()
     }
 
-    // todo 避免所有权问题先用了clone
+    /// Use `profile` for card clocks it contains, takes effect from next clock change
+    pub fn timing_profile_set(&mut self, profile: Option<MCITimingProfile>) {
+        self.timing_profile = profile;
+    }
+
+    pub fn timing_profile(&self) -> Option<MCITimingProfile> {
+        self.timing_profile
+    }
+
+    /// Whether the board timing profile has a DDR timing for `clk_hz`
+    pub fn ddr_timing_available(&self, clk_hz: u32) -> bool {
+        self.timing_profile
+            .and_then(|profile| profile.lookup(self.config.instance_id(), clk_hz, true))
+            .is_some()
+    }
+
+    /// Raw interrupt status seen by polling since current transfer started
+    pub(crate) fn raw_ints_seen(&self) -> u32 {
+        self.raw_ints_seen.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn raw_ints_record(&self, raw_ints: u32) {
+        self.raw_ints_seen.fetch_or(raw_ints, Ordering::Relaxed);
+    }
+
+    /// 只记录命令和数据参数, 数据缓冲区仍归调用者所有
     pub fn cur_cmd_set(&mut self, cmd: &MCICmdData) {
-        self.cur_cmd = Some(cmd.clone());
+        self.cur_cmd = Some(cmd.header());
     }
 
     /// initialization SDIF controller instance
@@ -146,7 +187,7 @@ impl MCI {
     }
 
     /// Setup DMA descriptor for SDIF controller instance
-    pub fn set_idma_list(&mut self, desc: &PoolBuffer, desc_num: u32) -> MCIResult {
+    pub fn set_idma_list(&mut self, desc: &mut DmaBuf<FSdifIDmaDesc>, desc_num: u32) -> MCIResult {
         if !self.is_ready {
             error!("Device is not yet initialized!");
             return Err(MCIError::NotInit);
@@ -157,9 +198,17 @@ impl MCI {
             return Err(MCIError::InvalidState);
         }
 
-        let bus_addr = mmap(desc.addr());
-        self.desc_list.first_desc_dma = bus_addr as usize;
-        self.desc_list.first_desc = desc.addr().as_ptr() as *mut FSdifIDmaDesc;
+        if desc_num as usize > desc.len() {
+            error!(
+                "Descriptor buffer too small! desc need: {}, desc available: {}",
+                desc_num,
+                desc.len()
+            );
+            return Err(MCIError::ShortBuf);
+        }
+
+        self.desc_list.first_desc_dma = desc.bus_addr() as usize;
+        self.desc_list.first_desc = desc.as_mut_ptr();
         self.desc_list.desc_num = desc_num;
         self.desc_list.desc_trans_sz = FSDIF_IDMAC_MAX_BUF_SIZE;
 
@@ -168,8 +217,9 @@ impl MCI {
         Ok(())
     }
 
-    /// Set the Card clock freqency
-    pub fn clk_freq_set(&mut self, clk_hz: u32) -> MCIResult {
+    /// Set the Card clock freqency, returns the achieved clock which is the closest
+    /// one not above `clk_hz`
+    pub fn clk_freq_set(&mut self, clk_hz: u32) -> MCIResult<u32> {
         let reg = self.config.reg();
         let mut reg_val = MCICmd::UPD_CLK;
 
@@ -181,13 +231,31 @@ impl MCI {
             reg_val |= MCICmd::VOLT_SWITCH;
         }
 
+        let mut actual_hz = 0;
         if clk_hz > 0 {
-            /* select board-related time-tuning configurations */
-            let target_timing = MCIConfig::get_tuning(clk_hz.into(), self.config.non_removable())
+            /* select board-related time-tuning configurations, there is no built-in DDR
+             * timing so DDR mode needs a board DDR entry */
+            let ddr = reg.read_reg::<MCIUhsReg>().contains(MCIUhsReg::DDR);
+            if ddr && !self.ddr_timing_available(clk_hz) {
+                error!("No DDR timing for clk {} !!!", clk_hz);
+                return Err(MCIError::InvalidTiming);
+            }
+            let (target_timing, achieved_hz) = self
+                .timing_profile
+                .and_then(|profile| profile.lookup(self.config.instance_id(), clk_hz, ddr))
+                .map(|timing| {
+                    let rate = timing.rate();
+                    (timing, rate)
+                })
+                .or_else(|| MCIConfig::timing_get(clk_hz, self.config.non_removable()))
                 .ok_or_else(|| {
-                error!("No available timing !!!");
-                MCIError::InvalidTiming
-            })?;
+                    error!("No available timing !!!");
+                    MCIError::InvalidTiming
+                })?;
+            if achieved_hz != clk_hz {
+                info!("clk {} not available, use {}", clk_hz, achieved_hz);
+            }
+            actual_hz = achieved_hz;
             /* update pad delay */
             target_timing.pad_delay(self.io_pad.as_mut().unwrap(), self.config.instance_id());
 
@@ -247,12 +315,13 @@ impl MCI {
 
             self.curr_timing = MCITiming::new();
         }
-        Ok(())
+        Ok(actual_hz)
     }
 
     /// Start command and data transfer in DMA mode
     pub fn dma_transfer(&mut self, cmd_data: &mut MCICmdData) -> MCIResult {
         cmd_data.success_set(false);
+        self.raw_ints_seen.store(0, Ordering::Relaxed);
         self.cur_cmd_set(&cmd_data);
 
         if !self.is_ready {
@@ -278,6 +347,8 @@ impl MCI {
         self.config
             .reg()
             .write_reg(MCIRawInts::from_bits_truncate(0xFFFFE));
+        /* 轮询模式下没有中断处理函数清除 IDMAC 状态, 避免上次的错误残留 */
+        self.dma_status_clear();
 
         /* reset fifo and DMA before transfer */
         self.ctrl_reset(MCICtrl::FIFO_RESET | MCICtrl::DMA_RESET)?;
@@ -295,19 +366,53 @@ impl MCI {
 
         // transfer command
         self.cmd_transfer(&cmd_data)?;
-        info!("dma cmd transfer ok");
+        debug!("dma cmd transfer ok");
         Ok(())
     }
 
-    /// Wait DMA transfer finished by poll
-    pub fn poll_wait_dma_end(&mut self, cmd_data: &mut MCICmdData) -> MCIResult {
-        let wait_bits = if cmd_data.get_data().is_none() {
-            MCIIntMask::CMD_BIT.bits()
+    /// Check once whether the transfer started by [`MCI::dma_transfer`] or [`MCI::pio_transfer`]
+    /// is finished, raw status is cleared when it returns `Ok`
+    pub fn poll(&mut self, cmd_data: &MCICmdData) -> nb::Result<(), MCIError> {
+        if !self.is_ready {
+            error!("Device is not yet initialized!");
+            return Err(nb::Error::Other(MCIError::NotInit));
+        }
+
+        let read = cmd_data.flag().contains(MCICmdFlag::READ_DATA);
+        /* PIO 模式下写数据在发命令前已填入 FIFO, 只需等待命令完成 */
+        let wait_bits = if cmd_data.get_data().is_some()
+            && (self.config.trans_mode() == MCITransMode::DMA || read)
+        {
+            MCIRawInts::CMD_BIT.bits() | MCIRawInts::DTO_BIT.bits()
         } else {
-            MCIIntMask::CMD_BIT.bits() | MCIIntMask::DTO_BIT.bits()
+            MCIRawInts::CMD_BIT.bits()
         };
-        let mut reg_val;
 
+        if let Some(err) = self.transfer_error_take(cmd_data) {
+            return Err(nb::Error::Other(err));
+        }
+
+        let reg_val = self.config.reg().read_reg::<MCIRawInts>().bits();
+        self.raw_ints_record(reg_val);
+        trace!("reg_val = 0x{:x}, wait_bits: 0x{:x}", reg_val, wait_bits);
+        if wait_bits & reg_val != wait_bits {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        if cmd_data.get_data().is_some() && !read {
+            unsafe {
+                dsb();
+            }
+        }
+
+        /* clear status to ack data done */
+        self.raw_status_clear();
+
+        Ok(())
+    }
+
+    /// Wait DMA transfer finished by poll
+    pub fn poll_wait_dma_end(&mut self, cmd_data: &MCICmdData) -> MCIResult {
         if !self.is_ready {
             error!("Device is not yet initialized!");
             return Err(MCIError::NotInit);
@@ -319,45 +424,22 @@ impl MCI {
         }
 
         /* wait command done or data timeout */
-        let mut delay = RETRIES_TIMEOUT;
+        let deadline = Deadline::after(cmd_data.timeout());
         loop {
-            reg_val = self.config.reg().read_reg::<MCIRawInts>().bits();
-
-            if delay != 0 {
-                debug!(
-                    "reg_val = 0x{:x}, delay: {}, wait_bits: {}, result: {}",
-                    reg_val,
-                    delay,
-                    wait_bits,
-                    wait_bits & reg_val == wait_bits
-                );
-            }
-
-            if wait_bits & reg_val == wait_bits || delay == 0 {
-                break;
-            }
-
-            delay -= 1;
-        }
-
-        if wait_bits & reg_val != wait_bits && delay == 0 {
-            error!("Wait command done timeout, raw ints: 0x{reg_val:x}!");
-            return Err(MCIError::CmdTimeout);
-        }
-
-        if cmd_data.get_data().is_some() {
-            let read = cmd_data.flag().contains(MCICmdFlag::READ_DATA);
-            if !read {
-                unsafe {
-                    dsb();
+            let expired = deadline.is_expired();
+            match self.poll(cmd_data) {
+                Ok(()) => return Ok(()),
+                Err(nb::Error::Other(err)) => return Err(err),
+                Err(nb::Error::WouldBlock) if expired => {
+                    error!(
+                        "Wait command done timeout, raw ints: 0x{:x}!",
+                        self.raw_status_get()
+                    );
+                    return Err(MCIError::CmdTimeout);
                 }
+                Err(nb::Error::WouldBlock) => {}
             }
         }
-
-        /* clear status to ack data done */
-        self.raw_status_clear();
-
-        Ok(())
     }
 
     /// Start command and data transfer in PIO mode
@@ -366,6 +448,7 @@ impl MCI {
         let reg = self.config.reg();
 
         cmd_data.success_set(false);
+        self.raw_ints_seen.store(0, Ordering::Relaxed);
 
         if !self.is_ready {
             error!("device is not yet initialized!!!");
@@ -385,6 +468,9 @@ impl MCI {
         /* wait previous command finished and card not busy */
         self.poll_wait_busy_card()?;
 
+        /* 清除原始中断寄存器, 避免上次的错误残留 */
+        self.raw_status_clear();
+
         /* reset fifo and not use DMA */
         reg.clear_reg(MCICtrl::USE_INTERNAL_DMAC);
         self.ctrl_reset(MCICtrl::FIFO_RESET)?;
@@ -392,6 +478,11 @@ impl MCI {
 
         /* transfer data */
         if let Some(data) = cmd_data.get_mut_data() {
+            if data.sg().is_some() {
+                error!("scatter/gather transfer is only available in DMA mode.");
+                return Err(MCIError::NotSupport);
+            }
+
             /* while in PIO mode, max data transferred is 0x800 */
             if data.datalen() > MCI_MAX_FIFO_CNT {
                 error!(
@@ -417,7 +508,7 @@ impl MCI {
     }
 
     /// Wait PIO transfer finished by poll
-    pub fn poll_wait_pio_end(&mut self, cmd_data: &mut MCICmdData) -> MCIResult {
+    pub fn poll_wait_pio_end(&mut self, cmd_data: &MCICmdData) -> MCIResult {
         let read = cmd_data.flag().contains(MCICmdFlag::READ_DATA);
         let reg = self.config.reg();
 
@@ -431,14 +522,22 @@ impl MCI {
             return Err(MCIError::InvalidState);
         }
 
+        /* 出现错误时不再等待完成位 */
+        let failed =
+            |reg: MCIRawInts| MCIError::from_status(reg.bits(), 0, cmd_data.cmdidx()).is_some();
+
         trace!("wait for PIO cmd to finish ...");
-        if let Err(err) = reg.retry_for(
+        let result = reg.retry_for(
             |reg: MCIRawInts| {
                 sleep(Duration::from_micros(10));
-                reg.contains(MCIRawInts::CMD_BIT)
+                reg.contains(MCIRawInts::CMD_BIT) || failed(reg)
             },
-            Some(RETRIES_TIMEOUT),
-        ) {
+            Some(cmd_data.timeout()),
+        );
+        if let Some(err) = self.transfer_error_take(cmd_data) {
+            return Err(err);
+        }
+        if let Err(err) = result {
             error!(
                 "wait cmd done timeout, raw ints: 0x{:x}",
                 self.raw_status_get()
@@ -449,13 +548,17 @@ impl MCI {
         /* if need to read data, read fifo after send command */
         if cmd_data.get_data().is_some() && read {
             trace!("wait for PIO data to read ...");
-            if let Err(err) = reg.retry_for(
+            let result = reg.retry_for(
                 |reg: MCIRawInts| {
                     sleep(Duration::from_micros(10));
-                    (MCIRawInts::DTO_BIT & reg).bits() != 0
+                    (MCIRawInts::DTO_BIT & reg).bits() != 0 || failed(reg)
                 },
-                Some(RETRIES_TIMEOUT),
-            ) {
+                Some(cmd_data.timeout()),
+            );
+            if let Some(err) = self.transfer_error_take(cmd_data) {
+                return Err(err);
+            }
+            if let Err(err) = result {
                 self.raw_status_clear();
                 return Err(err);
             }
@@ -467,6 +570,7 @@ impl MCI {
             );
         }
 
+        self.raw_ints_record(self.raw_status_get().bits());
         self.raw_status_clear();
         Ok(())
     }
@@ -657,7 +761,7 @@ impl MCI {
                 MCI::relax_handler();
                 result
             },
-            Some(RETRIES_TIMEOUT),
+            Some(CARD_BUSY_TIMEOUT),
         ) {
             error!("Wait card busy timeout !!!");
             return Err(err);
@@ -675,7 +779,7 @@ impl MCI {
                 reg.set_reg(MCICtrl::CONTROLLER_RESET);
                 !reg_val.contains(MCIStatus::DATA_BUSY)
             },
-            Some(RETRIES_TIMEOUT),
+            Some(CARD_BUSY_TIMEOUT),
         )?;
 
         Ok(())
@@ -685,10 +789,7 @@ impl MCI {
         let reg = self.config.reg();
 
         /* wait command finish if previous command is in error state */
-        reg.retry_for(
-            |reg| (MCICmd::START & reg).bits() == 0,
-            Some(RETRIES_TIMEOUT),
-        )?;
+        reg.retry_for(|reg| (MCICmd::START & reg).bits() == 0, Some(REG_TIMEOUT))?;
 
         /* update clock */
         self.clock_set(false);
@@ -1,4 +1,5 @@
 use bitflags::bitflags;
+use core::time::Duration;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MCIHostCmdType {
@@ -122,7 +123,7 @@ bitflags! {
         const SWITCH_ERROR                  = 1 << 7;  // Switch error status bit
         const APPLICATION_COMMAND           = 1 << 5;  // Application command enabled status bit
         const AUTHENTICATION_SEQUENCE_ERROR = 1 << 3;  // Error in the sequence of authentication process
-        const ALL_ERROR_FLAG = 0xFFF90008;    // All error status bits
+        const ALL_ERROR_FLAG = 0xFDF90008;    // All error status bits, CARD_IS_LOCKED is a state rather than an error
     }
 }
 
@@ -176,6 +177,11 @@ pub(crate) const MCI_HOST_CLOCK_400KHZ: u32 = 400_000;
 pub(crate) const MCI_HOST_MAX_CMD_RETRIES: u32 = 10;
 pub(crate) const MCI_HOST_DEFAULT_BLOCK_SIZE: u32 = 512;
 pub(crate) const MCI_HOST_MAX_BLOCK_LENGTH: u32 = 4096;
+/* 数据缓冲区按 cache line 对齐, 避免 cache 维护影响相邻数据 */
+pub(crate) const MCI_HOST_DMA_BUF_ALIGN: usize = 64;
+/* 未指定时的数据阶段超时, 与 SD 规范读超时上限一致 */
+pub(crate) const MCI_HOST_DATA_TIMEOUT: Duration = Duration::from_millis(100);
+pub(crate) const MCI_HOST_CD_POLL_INTERVAL: Duration = Duration::from_millis(1);
 
 bitflags! {
     /// OCR register flags in SD card
@@ -1,3 +1,6 @@
+use crate::mci::MCIError;
+use crate::osa::FMempError;
+
 #[allow(unused)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MCIHostError {
@@ -46,8 +49,10 @@ pub enum MCIHostError {
     TuningFail,                        // Tuning fail
     SwitchVoltageFail,                 // Switch voltage fail
     SwitchVoltage18VFail33VSuccess,    // Switch voltage fail
+    SwitchVoltage33VFail,              // Switch voltage back to 3.3V fail
     ReTuningRequest,                   // Retuning request
     SetDriverStrengthFail,             // Set driver strength fail
+    SetCurrentLimitFail,               // Set current limit fail
     SetPowerClassFail,                 // Set power class fail
     HostNotReady,                      // Host controller not ready
     CardDetectFailed,                  // Card detect failed
@@ -58,6 +63,60 @@ pub enum MCIHostError {
     CardStatusBusy,                    // Card busy
     CardInitFailed,                    // Card init failed
     IrqInitFailed,                     // init irq failed
+    CardLocked,                        // Card is locked by password
+    LockUnlockFailed,                  // Lock/unlock (CMD42) failed
+    CardSuspended,                     // Card is suspended, resume it first
+    CardChanged,                       // Another card inserted while suspended
+    RecordingLost,                     // Speed class recording ended by card re-initialization
+    ResponseError,                     // Response error (RE)
+    ResponseCrcError,                  // Response CRC error (RCRC)
+    ResponseTimeout,                   // Response timeout (RTO)
+    DataCrcError,                      // Data CRC error (DCRC)
+    DataReadTimeout,                   // Data read timeout (DRTO)
+    DataStarvation,                    // Data starvation by host timeout (HTO)
+    FifoUnderOverrun,                  // FIFO underrun/overrun (FRUN)
+    HardwareLocked,                    // Hardware locked write error (HLE)
+    StartBitError,                     // Start-bit error (SBE)
+    EndBitError,                       // End-bit error or write no CRC status (EBE)
+    DmaBusError,                       // IDMAC fatal bus error (FBE)
+    DmaDescUnavailable,                // IDMAC descriptor unavailable (DU)
+    DmaBufAllocFailed,                 // Allocate DMA buffer failed
+}
+
+impl From<FMempError> for MCIHostError {
+    fn from(_: FMempError) -> Self {
+        MCIHostError::DmaBufAllocFailed
+    }
+}
+
+impl From<MCIError> for MCIHostError {
+    fn from(err: MCIError) -> Self {
+        match err {
+            MCIError::ResponseErr => MCIHostError::ResponseError,
+            MCIError::ResponseCrc => MCIHostError::ResponseCrcError,
+            MCIError::ResponseTimeout => MCIHostError::ResponseTimeout,
+            MCIError::DataCrc => MCIHostError::DataCrcError,
+            MCIError::DataReadTimeout => MCIHostError::DataReadTimeout,
+            MCIError::DataStarvation => MCIHostError::DataStarvation,
+            MCIError::FifoRun => MCIHostError::FifoUnderOverrun,
+            MCIError::HardwareLocked => MCIHostError::HardwareLocked,
+            MCIError::StartBit => MCIHostError::StartBitError,
+            MCIError::EndBit => MCIHostError::EndBitError,
+            MCIError::DmaBusFatal => MCIHostError::DmaBusError,
+            MCIError::DescUnavailable => MCIHostError::DmaDescUnavailable,
+            MCIError::Timeout | MCIError::TransTimeout | MCIError::CmdTimeout => {
+                MCIHostError::Timeout
+            }
+            MCIError::NoCard => MCIHostError::CardDetectFailed,
+            MCIError::Busy => MCIHostError::Busy,
+            MCIError::NotSupport => MCIHostError::HostNotSupport,
+            MCIError::NotInit
+            | MCIError::ShortBuf
+            | MCIError::InvalidState
+            | MCIError::DmaBufUnalign
+            | MCIError::InvalidTiming => MCIHostError::TransferFailed,
+        }
+    }
 }
 
 pub type MCIHostStatus<T = ()> = Result<T, MCIHostError>;
@@ -1,4 +1,4 @@
-use crate::osa::pool_buffer::PoolBuffer;
+use crate::osa::dma_buf::DmaBuf;
 
 use super::MCIHost;
 
@@ -6,7 +6,7 @@ pub(crate) struct MCICardBase {
     pub host: Option<MCIHost>,
     pub is_host_ready: bool,
     pub no_interal_align: bool,
-    pub internal_buffer: PoolBuffer,
+    pub internal_buffer: DmaBuf<u32>,
     pub bus_clk_hz: u32,
     pub relative_address: u32,
     pub ocr: u32,
@@ -14,7 +14,7 @@ pub(crate) struct MCICardBase {
 }
 
 impl MCICardBase {
-    pub fn from_buffer(buffer: PoolBuffer) -> Self {
+    pub fn from_buffer(buffer: DmaBuf<u32>) -> Self {
         MCICardBase {
             host: None,
             is_host_ready: false,
@@ -1,4 +1,9 @@
-use super::constants::MCIHostDetectCardType;
+use core::time::Duration;
+
+use super::constants::{MCIHostDetectCardType, MCI_HOST_CD_POLL_INTERVAL};
+use super::err::{MCIHostError, MCIHostStatus};
+use crate::tools::Deadline;
+use crate::{now, sleep};
 
 #[allow(unused)]
 pub struct MCIHostCardDetect {
@@ -22,4 +27,34 @@ impl MCIHostCardDetect {
             dat3_pull_func: None,
         }
     }
+
+    /// 等待卡检测状态变为期望值, 并连续保持 cd_debounce_ms 以消除抖动
+    pub(crate) fn wait_stable(
+        &self,
+        is_inserted: impl Fn() -> bool,
+        wait_inserted: bool,
+        timeout: Option<Duration>,
+    ) -> MCIHostStatus {
+        let debounce = Duration::from_millis(self.cd_debounce_ms as u64);
+        let deadline = timeout.map(Deadline::after);
+        let mut stable_since = None;
+
+        loop {
+            let expired = deadline.is_some_and(|d| d.is_expired());
+            if is_inserted() == wait_inserted {
+                let since = *stable_since.get_or_insert_with(now);
+                if now() - since >= debounce {
+                    return Ok(());
+                }
+            } else {
+                stable_since = None;
+            }
+
+            if expired {
+                return Err(MCIHostError::Timeout);
+            }
+
+            sleep(MCI_HOST_CD_POLL_INTERVAL);
+        }
+    }
 }
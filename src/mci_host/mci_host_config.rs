@@ -20,6 +20,7 @@ pub struct MCIHostConfig {
     pub(crate) def_block_size: usize,          // 默认块大小
     pub(crate) card_clock: u32,                // 卡时钟频率
     pub(crate) is_uhs_card: bool,              // 是否为 UHS 卡
+    pub(crate) recovery: MCIHostRecoveryPolicy, // 传输失败后的恢复策略
                                                /* for SDIO card, to support card customized interrupt handling */ // todo 暂时没实现这部分功能
                                                // todo timeTuner
 }
@@ -39,6 +40,7 @@ impl MCIHostConfig {
             def_block_size: SD_BLOCK_SIZE,
             card_clock: SD_CLOCK_50MHZ,
             is_uhs_card: false,
+            recovery: MCIHostRecoveryPolicy::default(),
         };
 
         if cfg!(feature = "dma") {
@@ -59,6 +61,30 @@ impl MCIHostConfig {
     }
 }
 
+/// 传输失败后的恢复策略, 按字段顺序逐级升级
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MCIHostRecoveryPolicy {
+    /// 复位 FIFO/IDMAC 后原样重试的次数
+    pub retries: u32,
+    /// SDR50/SDR104 下重新调谐采样点
+    pub retune: bool,
+    /// 逐级降低总线时钟, 最后退回 1 线模式
+    pub downgrade: bool,
+    /// 重新识别并初始化卡
+    pub reinit: bool,
+}
+
+impl Default for MCIHostRecoveryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            retune: true,
+            downgrade: true,
+            reinit: true,
+        }
+    }
+}
+
 #[allow(unused)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum MCIHostType {
@@ -0,0 +1,103 @@
+//! 控制器命令统计和延迟直方图
+use core::time::Duration;
+
+use super::err::MCIHostError;
+
+/// 直方图桶数, 第 i 个桶统计小于 `16us << i` 的延迟, 最后一个桶统计其余所有延迟
+pub const MCI_HOST_LATENCY_BUCKETS: usize = 16;
+
+/// 按 2 的幂划分的延迟直方图
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MCIHostLatencyHistogram {
+    pub buckets: [u32; MCI_HOST_LATENCY_BUCKETS],
+    pub max: Duration,
+    pub total: Duration,
+}
+
+impl MCIHostLatencyHistogram {
+    /// Upper bound of bucket `index`, `None` for the last bucket
+    pub fn bucket_limit(index: usize) -> Option<Duration> {
+        (index + 1 < MCI_HOST_LATENCY_BUCKETS).then(|| Duration::from_micros(16 << index))
+    }
+
+    pub fn count(&self) -> u32 {
+        self.buckets.iter().sum()
+    }
+
+    pub fn average(&self) -> Duration {
+        match self.count() {
+            0 => Duration::ZERO,
+            count => self.total / count,
+        }
+    }
+
+    /// Upper bound of the bucket holding the `percent` percentile, [`Self::max`] for the
+    /// last bucket
+    pub fn percentile(&self, percent: u32) -> Duration {
+        let count = self.count() as u64;
+        let target = (count * percent.min(100) as u64).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (index, &bucket) in self.buckets.iter().enumerate() {
+            seen += bucket as u64;
+            if seen >= target {
+                return Self::bucket_limit(index).map_or(self.max, |limit| limit.min(self.max));
+            }
+        }
+        self.max
+    }
+
+    pub(crate) fn record(&mut self, latency: Duration) {
+        let index = (0..MCI_HOST_LATENCY_BUCKETS)
+            .find(|&index| Self::bucket_limit(index).is_none_or(|limit| latency < limit))
+            .unwrap();
+        self.buckets[index] = self.buckets[index].saturating_add(1);
+        self.max = self.max.max(latency);
+        self.total += latency;
+    }
+}
+
+/// 控制器层面的命令统计, 包含恢复过程中发出的命令
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MCIHostCmdStats {
+    pub cmds: u64,
+    pub cmd_errors: u64,
+    /// 命令响应或数据 CRC 错误
+    pub crc_errors: u64,
+    /// 响应, 数据或等待完成超时
+    pub timeouts: u64,
+    /// 从发出到完成的延迟, 不含数据的命令
+    pub cmd_latency: MCIHostLatencyHistogram,
+    /// 从发出到完成的延迟, 带数据的命令
+    pub data_latency: MCIHostLatencyHistogram,
+}
+
+impl MCIHostCmdStats {
+    pub(crate) fn issue_record(&mut self) {
+        self.cmds += 1;
+    }
+
+    pub(crate) fn complete_record(
+        &mut self,
+        has_data: bool,
+        latency: Duration,
+        error: Option<MCIHostError>,
+    ) {
+        if has_data {
+            self.data_latency.record(latency);
+        } else {
+            self.cmd_latency.record(latency);
+        }
+
+        let Some(error) = error else {
+            return;
+        };
+        self.cmd_errors += 1;
+        match error {
+            MCIHostError::ResponseCrcError | MCIHostError::DataCrcError => self.crc_errors += 1,
+            MCIHostError::Timeout
+            | MCIHostError::ResponseTimeout
+            | MCIHostError::DataReadTimeout => self.timeouts += 1,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! 命令跟踪: 每条命令发出和完成时通知观察者, 并可记录到固定大小的环形缓冲区
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use log::*;
+
+use super::err::MCIHostError;
+use crate::mci::MCIRegisterSnapshot;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MCIHostTracePhase {
+    Issue,
+    Complete,
+}
+
+/// 一条命令的跟踪事件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MCIHostTraceEvent {
+    pub phase: MCIHostTracePhase,
+    pub index: u32,
+    pub argument: u32,
+    /// MCICmdFlag 标志位
+    pub flags: u32,
+    /// 完成时的响应, 发出时为 0
+    pub response: [u32; 4],
+    /// 命令期间的原始中断状态, 发出时为 0
+    pub raw_ints: u32,
+    /// 数据阶段字节数, 无数据时为 0
+    pub data_len: u32,
+    /// 事件发生的时刻
+    pub time: Duration,
+    /// 从发出到完成的耗时, 发出时为 0
+    pub duration: Duration,
+    /// 失败时的错误
+    pub error: Option<MCIHostError>,
+    /// 失败时的控制器寄存器
+    pub regs: Option<MCIRegisterSnapshot>,
+}
+
+/// Observer of every command, called in transfer context with the controller locked,
+/// so keep it short
+pub trait MCIHostObserver: Send + Sync {
+    fn cmd_issue(&self, _event: &MCIHostTraceEvent) {}
+    fn cmd_complete(&self, _event: &MCIHostTraceEvent) {}
+}
+
+/// 最近的跟踪事件, 写满后覆盖最旧的事件
+pub struct MCIHostTraceRing {
+    events: Vec<MCIHostTraceEvent>,
+    capacity: usize,
+    next: usize,
+}
+
+impl MCIHostTraceRing {
+    pub fn new(capacity: usize) -> Self {
+        MCIHostTraceRing {
+            events: Vec::with_capacity(capacity),
+            capacity,
+            next: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, event: MCIHostTraceEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() < self.capacity {
+            self.events.push(event);
+        } else {
+            self.events[self.next] = event;
+        }
+        self.next = (self.next + 1) % self.capacity;
+    }
+
+    /// Events from the oldest to the newest
+    pub fn events(&self) -> Vec<MCIHostTraceEvent> {
+        if self.events.len() < self.capacity {
+            return self.events.clone();
+        }
+        let (newer, older) = self.events.split_at(self.next);
+        older.iter().chain(newer).copied().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.next = 0;
+    }
+
+    /// Log all events, from the oldest to the newest
+    pub fn dump(&self) {
+        warn!("---- last {} command(s) ----", self.events.len());
+        for event in self.events() {
+            match event.phase {
+                MCIHostTracePhase::Issue => warn!(
+                    "[{:?}] CMD{} issue, arg 0x{:x}, flags 0x{:x}, data {} bytes",
+                    event.time, event.index, event.argument, event.flags, event.data_len
+                ),
+                MCIHostTracePhase::Complete => warn!(
+                    "[{:?}] CMD{} {:?} in {:?}, resp {:x?}, raw ints 0x{:x}",
+                    event.time,
+                    event.index,
+                    event.error,
+                    event.duration,
+                    event.response,
+                    event.raw_ints
+                ),
+            }
+            if let Some(regs) = event.regs.as_ref() {
+                warn!("{}", regs);
+            }
+        }
+    }
+}
@@ -1,6 +1,10 @@
 use alloc::vec::Vec;
+use core::time::Duration;
 
 use super::constants::*;
+use super::err::MCIHostStatus;
+use crate::mci::mci_dma::MCIDmaSegment;
+use crate::osa::dma_buf::DmaBuf;
 
 pub struct MCIHostTransfer {
     data: Option<MCIHostData>,
@@ -42,15 +46,18 @@ impl MCIHostTransfer {
 
 #[allow(unused)]
 pub(crate) struct MCIHostData {
-    stream_transfer: bool,       // 指示是否为流数据传输命令
-    enable_auto_command12: bool, // 启用自动 CMD12
-    enable_auto_command23: bool, // 启用自动 CMD23
-    enable_ignore_error: bool,   // 启用忽略错误以读取/写入所有数据
-    data_type: u8,               // 用于区分普通/调谐/启动数据
-    block_size: usize,           // 块大小
-    block_count: u32,            // 块数量
-    rx_data: Option<Vec<u32>>,   // 用于保存读取数据的缓冲区
-    tx_data: Option<Vec<u32>>,   // 用于写入数据的缓冲区
+    stream_transfer: bool,             // 指示是否为流数据传输命令
+    enable_auto_command12: bool,       // 启用自动 CMD12
+    enable_auto_command23: bool,       // 启用自动 CMD23
+    enable_ignore_error: bool,         // 启用忽略错误以读取/写入所有数据
+    data_type: u8,                     // 用于区分普通/调谐/启动数据
+    block_size: usize,                 // 块大小
+    block_count: u32,                  // 块数量
+    rx_data: Option<DmaBuf<u32>>,      // 用于保存读取数据的缓冲区, 由 DMA 内存提供者分配
+    tx_data: Option<DmaBuf<u32>>,      // 用于写入数据的缓冲区, 由 DMA 内存提供者分配
+    rx_sg: Option<Vec<MCIDmaSegment>>, // 分散/聚集读取的物理段, 仅 DMA 模式
+    tx_sg: Option<Vec<MCIDmaSegment>>, // 分散/聚集写入的物理段, 仅 DMA 模式
+    timeout: Duration,                 // 数据阶段超时
 }
 
 #[allow(unused)]
@@ -66,6 +73,9 @@ impl MCIHostData {
             block_count: 0,
             rx_data: None,
             tx_data: None,
+            rx_sg: None,
+            tx_sg: None,
+            timeout: MCI_HOST_DATA_TIMEOUT,
         }
     }
 
@@ -85,6 +95,10 @@ impl MCIHostData {
         self.enable_auto_command23
     }
 
+    pub(crate) fn enable_auto_command23_set(&mut self, enable_auto_command23: bool) {
+        self.enable_auto_command23 = enable_auto_command23
+    }
+
     pub(crate) fn enable_ignore_error(&self) -> bool {
         self.enable_ignore_error
     }
@@ -109,37 +123,80 @@ impl MCIHostData {
         self.block_count = block_count;
     }
 
-    pub(crate) fn rx_data(&self) -> Option<&Vec<u32>> {
+    pub(crate) fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub(crate) fn timeout_set(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub(crate) fn rx_data(&self) -> Option<&DmaBuf<u32>> {
         self.rx_data.as_ref()
     }
 
-    pub(crate) fn rx_data_set(&mut self, rx_data: Option<Vec<u32>>) {
+    pub(crate) fn rx_data_set(&mut self, rx_data: Option<DmaBuf<u32>>) {
         self.rx_data = rx_data
     }
 
-    pub(crate) fn rx_data_mut(&mut self) -> Option<&mut Vec<u32>> {
+    pub(crate) fn rx_data_mut(&mut self) -> Option<&mut DmaBuf<u32>> {
         self.rx_data.as_mut()
     }
 
-    pub(crate) fn rx_data_take(&mut self) -> Option<Vec<u32>> {
+    pub(crate) fn rx_data_take(&mut self) -> Option<DmaBuf<u32>> {
         self.rx_data.take()
     }
 
-    pub(crate) fn tx_data(&self) -> Option<&Vec<u32>> {
+    /// 分配 `words` 个字的接收缓冲区
+    pub(crate) fn rx_data_alloc(&mut self, words: usize) -> MCIHostStatus {
+        self.rx_data = Some(DmaBuf::zeroed(words, MCI_HOST_DMA_BUF_ALIGN)?);
+        Ok(())
+    }
+
+    pub(crate) fn tx_data(&self) -> Option<&DmaBuf<u32>> {
         self.tx_data.as_ref()
     }
 
-    pub(crate) fn tx_data_mut(&mut self) -> Option<&mut Vec<u32>> {
+    pub(crate) fn tx_data_mut(&mut self) -> Option<&mut DmaBuf<u32>> {
         self.tx_data.as_mut()
     }
 
-    pub(crate) fn tx_data_set(&mut self, tx_data: Option<Vec<u32>>) {
+    pub(crate) fn tx_data_set(&mut self, tx_data: Option<DmaBuf<u32>>) {
         self.tx_data = tx_data
     }
 
-    pub(crate) fn tx_data_take(&mut self) -> Option<Vec<u32>> {
+    pub(crate) fn tx_data_take(&mut self) -> Option<DmaBuf<u32>> {
         self.tx_data.take()
     }
+
+    /// 分配发送缓冲区并复制 `src`, 控制器只能访问 DMA 内存
+    pub(crate) fn tx_data_copy(&mut self, src: &[u32]) -> MCIHostStatus {
+        let mut tx_data = DmaBuf::new(src.len(), MCI_HOST_DMA_BUF_ALIGN)?;
+        tx_data.copy_from_slice(src)?;
+        self.tx_data = Some(tx_data);
+        Ok(())
+    }
+
+    pub(crate) fn rx_sg_set(&mut self, rx_sg: Option<Vec<MCIDmaSegment>>) {
+        self.rx_sg = rx_sg
+    }
+
+    pub(crate) fn rx_sg(&self) -> Option<&Vec<MCIDmaSegment>> {
+        self.rx_sg.as_ref()
+    }
+
+    pub(crate) fn tx_sg_set(&mut self, tx_sg: Option<Vec<MCIDmaSegment>>) {
+        self.tx_sg = tx_sg
+    }
+
+    pub(crate) fn tx_sg(&self) -> Option<&Vec<MCIDmaSegment>> {
+        self.tx_sg.as_ref()
+    }
+
+    /// 是否为分散/聚集请求
+    pub(crate) fn is_sg(&self) -> bool {
+        self.rx_sg.is_some() || self.tx_sg.is_some()
+    }
 }
 
 #[allow(unused)]
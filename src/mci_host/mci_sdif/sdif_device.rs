@@ -1,50 +1,86 @@
 #![allow(dead_code)]
 
-use core::cell::{Cell, RefCell};
-use core::mem::take;
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use core::time::Duration;
 
+use alloc::boxed::Box;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use log::*;
+use spin::Mutex;
 
 use super::consts::SDStatus;
 use super::MCIHost;
 use crate::aarch::dsb;
-use crate::invalidate;
+use crate::dma::{DmaDirection, DmaRegion};
 use crate::mci::consts::*;
 use crate::mci::mci_data::MCIData;
 use crate::mci::mci_dma::FSdifIDmaDesc;
 use crate::mci::regs::MCIIntMask;
-use crate::mci::{MCICmdData, MCIConfig, MCI};
+use crate::mci::{
+    MCICmdData, MCIConfig, MCIIrqHandler, MCIIrqState, MCIRegisterSnapshot, MCITimingProfile, MCI,
+};
 use crate::mci_host::constants::*;
 use crate::mci_host::err::*;
 use crate::mci_host::mci_host_card_detect::MCIHostCardDetect;
 use crate::mci_host::mci_host_config::*;
+use crate::mci_host::mci_host_stats::MCIHostCmdStats;
+use crate::mci_host::mci_host_trace::{
+    MCIHostObserver, MCIHostTraceEvent, MCIHostTracePhase, MCIHostTraceRing,
+};
 use crate::mci_host::mci_host_transfer::MCIHostTransfer;
 use crate::mci_host::sd::consts::SdCmd;
 use crate::mci_host::MCIHostCardIntFn;
-use crate::osa::pool_buffer::PoolBuffer;
+use crate::osa::dma_buf::DmaBuf;
+use crate::osa::{osa_mutex_create, osa_mutex_lock, OsaMutex, OsaMutexGuard};
 use crate::sd::consts::SD_BLOCK_SIZE;
-use crate::tools::swap_half_word_byte_sequence_u32;
-use crate::{flush, mmap, sleep, IoPad};
+use crate::tools::{swap_half_word_byte_sequence_u32, Deadline};
+use crate::{now, IoPad};
 
 pub(crate) struct SDIFDev {
-    /// SDIF 硬件控制器
-    hc: RefCell<MCI>,
+    /// 命令锁, 串行化并发的调用者, RTOS 下等待的线程会让出 CPU
+    cmd_lock: Box<dyn OsaMutex>,
+    /// SDIF 硬件控制器, 从发出命令到取回响应期间保持上锁
+    hc: Mutex<MCI>,
     /// SDIF 配置
-    hc_cfg: RefCell<MCIConfig>,
+    hc_cfg: Mutex<MCIConfig>,
     /// DMA 描述符指针，用于管理数据传输
-    rw_desc: PoolBuffer,
+    rw_desc: Mutex<DmaBuf<FSdifIDmaDesc>>,
     /// 描述符数量，表示 DMA 描述符的数量
-    desc_num: Cell<u32>,
+    desc_num: AtomicU32,
+    /// 与中断处理函数共享的状态
+    irq: Arc<MCIIrqState>,
+    /// 已发出但尚未取回结果的非阻塞传输
+    pending: Mutex<Option<SDIFPending>>,
+    /// 命令观察者
+    observer: Mutex<Option<Arc<dyn MCIHostObserver>>>,
+    /// 最近命令的环形缓冲区
+    trace_ring: Mutex<Option<MCIHostTraceRing>>,
+    /// 当前命令发出的时刻
+    issue_time: Mutex<Duration>,
+    /// 命令统计
+    stats: Mutex<MCIHostCmdStats>,
+    /// 最近一次失败时的寄存器
+    error_regs: Mutex<Option<MCIRegisterSnapshot>>,
+    /// 注入的故障及剩余次数, 用于测试恢复流程
+    #[cfg(feature = "fault-inject")]
+    fault: Mutex<Option<(MCIHostError, u32)>>,
+    /// 下一条命令前先发送 80 个周期的初始化时钟
+    init_clock_pending: AtomicBool,
+    /// DDR 模式改变后, 即使频率不变也要按新的时序重新配置时钟
+    clock_stale: AtomicBool,
+}
+
+struct SDIFPending {
+    cmd_data: MCICmdData,
+    deadline: Deadline,
 }
 
 impl SDIFDev {
     pub fn new(addr: NonNull<u8>, desc_num: usize) -> Self {
         let align = SD_BLOCK_SIZE;
-        let size = core::mem::size_of::<FSdifIDmaDesc>() * desc_num;
-        let rw_desc = match PoolBuffer::new(size, align) {
+        let rw_desc = match DmaBuf::<FSdifIDmaDesc>::zeroed(desc_num, align) {
             Err(e) => {
                 panic!("alloc internal buffer failed! err: {:?}", e);
             }
@@ -53,18 +89,45 @@ impl SDIFDev {
         debug!(
             "rw_desc buffer at {:x}, pa {:x}",
             rw_desc.addr().as_ptr() as usize,
-            mmap(rw_desc.addr())
+            rw_desc.bus_addr()
         );
 
+        let irq = Arc::new(MCIIrqState::new(addr));
+        MCIIrqState::legacy_register(&irq);
+
         Self {
+            cmd_lock: osa_mutex_create(),
             hc: MCI::new(MCIConfig::new(addr)).into(),
             hc_cfg: MCIConfig::new(addr).into(),
-            rw_desc,
+            rw_desc: rw_desc.into(),
             desc_num: (desc_num as u32).into(),
+            irq,
+            pending: Mutex::new(None),
+            observer: Mutex::new(None),
+            trace_ring: Mutex::new(None),
+            issue_time: Mutex::new(Duration::ZERO),
+            stats: Mutex::new(MCIHostCmdStats::default()),
+            error_regs: Mutex::new(None),
+            #[cfg(feature = "fault-inject")]
+            fault: Mutex::new(None),
+            init_clock_pending: AtomicBool::new(false),
+            clock_stale: AtomicBool::new(false),
         }
     }
     pub fn iopad_set(&self, iopad: IoPad) {
-        self.hc.borrow_mut().iopad_set(iopad);
+        self.hc.lock().iopad_set(iopad);
+    }
+
+    pub fn timing_profile_set(&self, profile: Option<MCITimingProfile>) {
+        self.hc.lock().timing_profile_set(profile);
+    }
+
+    pub fn ddr_timing_available(&self, clk_hz: u32) -> bool {
+        self.hc.lock().ddr_timing_available(clk_hz)
+    }
+
+    pub fn irq_handler(&self) -> MCIIrqHandler {
+        MCIIrqHandler::new(self.irq.clone())
     }
 }
 
@@ -72,7 +135,7 @@ impl SDIFDev {
 impl SDIFDev {
     pub fn whether_transfer_data(&self) -> bool {
         self.hc
-            .borrow()
+            .lock()
             .cur_cmd()
             .as_ref()
             .unwrap()
@@ -84,7 +147,7 @@ impl SDIFDev {
 impl SDIFDev {
     pub fn init(&self, addr: NonNull<u8>, host: &MCIHost) -> MCIHostStatus {
         let num_of_desc = host.config.max_trans_size / host.config.def_block_size;
-        self.desc_num.set(num_of_desc as u32);
+        self.desc_num.store(num_of_desc as u32, Ordering::Relaxed);
         self.do_init(addr, host)?;
         Ok(())
     }
@@ -92,14 +155,12 @@ impl SDIFDev {
     fn do_init(&self, addr: NonNull<u8>, host: &MCIHost) -> MCIHostStatus {
         info!("dev do init");
         let mci_config = MCIConfig::lookup_config(addr);
-        let iopad = self
-            .hc
-            .borrow_mut()
-            .iopad_take()
-            .ok_or(MCIHostError::NoData)?;
+        let iopad = self.hc.lock().iopad_take().ok_or(MCIHostError::NoData)?;
+        let timing_profile = self.hc.lock().timing_profile();
 
-        *self.hc.borrow_mut() = MCI::new(MCIConfig::lookup_config(addr));
-        self.hc.borrow_mut().iopad_set(iopad);
+        *self.hc.lock() = MCI::new(MCIConfig::lookup_config(addr));
+        self.hc.lock().iopad_set(iopad);
+        self.hc.lock().timing_profile_set(timing_profile);
 
         // 强行 restart 一下
         let restart_mci = MCI::new_restart(MCIConfig::restart(addr));
@@ -107,35 +168,34 @@ impl SDIFDev {
             .restart()
             .unwrap_or_else(|e| error!("restart failed: {:?}", e));
 
-        if let Err(_) = self.hc.borrow_mut().config_init(&mci_config) {
+        if let Err(_) = self.hc.lock().config_init(&mci_config) {
             info!("Sdio ctrl init failed.");
             return Err(MCIHostError::Fail);
         }
 
         if host.config.enable_dma {
-            if let Err(_) = self
-                .hc
-                .borrow_mut()
-                .set_idma_list(&self.rw_desc, self.desc_num.get())
-            {
+            if let Err(_) = self.hc.lock().set_idma_list(
+                &mut self.rw_desc.lock(),
+                self.desc_num.load(Ordering::Relaxed),
+            ) {
                 error!("idma list set failed!");
                 return Err(MCIHostError::Fail);
             }
         }
 
         // self.register_event_arg();
-        *self.hc_cfg.borrow_mut() = mci_config;
+        *self.hc_cfg.lock() = mci_config;
         info!("do_init ok");
         Ok(())
     }
 
     fn deinit(&self) {
-        let _ = self.hc.borrow_mut().config_deinit();
+        let _ = self.hc.lock().config_deinit();
         info!("Sdio ctrl deinited !!!")
     }
 
     pub fn reset(&self) -> MCIHostStatus {
-        match self.hc.borrow_mut().restart() {
+        match self.hc.lock().restart() {
             Ok(_) => Ok(()),
             Err(_) => Err(MCIHostError::Fail),
         }
@@ -148,18 +208,18 @@ impl SDIFDev {
     ) -> MCIHostStatus {
         match voltage {
             MCIHostOperationVoltage::Voltage300V => {
-                host.curr_voltage.set(voltage);
-                self.hc.borrow_mut().voltage_1_8v_set(false);
+                *host.curr_voltage.lock() = voltage;
+                self.hc.lock().voltage_1_8v_set(false);
                 info!("Switch to 3.0V");
             }
             MCIHostOperationVoltage::Voltage330V => {
-                host.curr_voltage.set(voltage);
-                self.hc.borrow_mut().voltage_1_8v_set(false);
+                *host.curr_voltage.lock() = voltage;
+                self.hc.lock().voltage_1_8v_set(false);
                 info!("Switch to 3.0V");
             }
             MCIHostOperationVoltage::Voltage180V => {
-                host.curr_voltage.set(voltage);
-                self.hc.borrow_mut().voltage_1_8v_set(true);
+                *host.curr_voltage.lock() = voltage;
+                self.hc.lock().voltage_1_8v_set(true);
                 info!("Switch to 1.8V");
             }
             _ => {
@@ -178,8 +238,9 @@ impl SDIFDev {
         Ok(())
     }
 
-    fn enable_ddr_mode(&self, enable: bool, _nibble_pos: u32) {
-        self.hc.borrow().set_ddr_mode(enable);
+    pub fn enable_ddr_mode(&self, enable: bool, _nibble_pos: u32) {
+        self.hc.lock().set_ddr_mode(enable);
+        self.clock_stale.store(true, Ordering::Relaxed);
     }
 
     fn enable_hs400_mode(&self, _enable: bool) {
@@ -191,12 +252,12 @@ impl SDIFDev {
     }
 
     fn get_signal_line_status(&self, _signal_line: u32) -> bool {
-        !self.hc.borrow().check_if_card_busy()
+        !self.hc.lock().check_if_card_busy()
     }
 
     pub fn convert_data_to_little_endian(
         &self,
-        data: &mut Vec<u32>,
+        data: &mut [u32],
         word_size: usize,
         format: MCIHostDataPacketFormat,
         host: &MCIHost,
@@ -228,11 +289,13 @@ impl SDIFDev {
         Ok(())
     }
 
-    pub fn card_power_set(&self, _enable: bool) {}
+    pub fn card_power_set(&self, enable: bool) {
+        self.hc.lock().power_set(enable);
+    }
 
     fn card_int_enable(&self, enable: bool, host: &MCIHost) -> MCIHostStatus {
         if MCIHostCardType::SDIO == host.config.card_type {
-            self.hc.borrow().interrupt_mask_set(
+            self.hc.lock().interrupt_mask_set(
                 MCIIntrType::GeneralIntr,
                 MCIIntMask::SDIO_BIT.bits(),
                 enable,
@@ -248,15 +311,15 @@ impl SDIFDev {
     pub fn card_bus_width_set(&self, data_bus_width: MCIHostBusWdith) {
         match data_bus_width {
             MCIHostBusWdith::Bit1 => {
-                self.hc.borrow().bus_width_set(data_bus_width as u32);
+                self.hc.lock().bus_width_set(data_bus_width as u32);
                 warn!("Set bus width to 1 bit");
             }
             MCIHostBusWdith::Bit4 => {
-                self.hc.borrow().bus_width_set(data_bus_width as u32);
+                self.hc.lock().bus_width_set(data_bus_width as u32);
                 warn!("Set bus width to 4 bit");
             }
             MCIHostBusWdith::Bit8 => {
-                self.hc.borrow().bus_width_set(data_bus_width as u32);
+                self.hc.lock().bus_width_set(data_bus_width as u32);
                 warn!("Set bus width to 8 bit");
             }
         }
@@ -265,68 +328,72 @@ impl SDIFDev {
     pub fn card_detect_status_polling(
         &self,
         wait_card_status: SDStatus,
-        _timeout: u32,
+        timeout: Duration,
         host: &MCIHost,
     ) -> MCIHostStatus {
         let cd = host.cd.as_ref().ok_or(MCIHostError::NoData)?;
 
-        let mut retry_times: usize = 100;
-
-        /* Wait card inserted. */
-        loop {
-            let is_card_inserted = self.card_detect_status() == SDStatus::Inserted;
-            sleep(Duration::from_millis(cd.cd_debounce_ms as u64));
-            if wait_card_status == SDStatus::Inserted && is_card_inserted {
-                break;
-            }
-
-            if wait_card_status == SDStatus::Removed && !is_card_inserted {
-                break;
-            }
-
-            if retry_times == 0 {
-                info!("Wait card insert timeout !!!");
-                return Err(MCIHostError::Timeout);
-            }
-
-            retry_times -= 1;
+        /* Wait card status stable */
+        if let Err(err) = cd.wait_stable(
+            || self.card_detect_status() == SDStatus::Inserted,
+            wait_card_status == SDStatus::Inserted,
+            Some(timeout),
+        ) {
+            info!("Wait card insert timeout !!!");
+            return Err(err);
         }
         Ok(())
     }
 
+    pub fn card_is_inserted(&self) -> bool {
+        self.card_detect_status() == SDStatus::Inserted
+    }
+
     fn card_detect_status(&self) -> SDStatus {
-        if self.hc.borrow().check_if_card_exist() {
+        if self.hc.lock().check_if_card_exist() {
             SDStatus::Inserted
         } else {
             SDStatus::Removed
         }
     }
 
-    pub fn card_active_send(&self) {}
+    /// 让卡在上电后收到至少 74 个时钟, 控制器只能随命令发出, 在下一条命令前发送
+    pub fn card_active_send(&self) {
+        self.init_clock_pending.store(true, Ordering::Relaxed);
+    }
 
     pub fn card_clock_set(&self, target_clock: u32, host: &MCIHost) -> u32 {
-        // 如果当前时钟频率已经是目标频率，则直接返回
-        if host.curr_clock_freq.get() == target_clock {
-            return host.curr_clock_freq.get();
-        }
-        // 尝试设置时钟频率
-        if self.hc.borrow_mut().clk_freq_set(target_clock).is_ok() {
-            info!("BUS CLOCK: {}", target_clock);
-            // 更新实例的时钟频率
-            host.curr_clock_freq.set(target_clock);
-        } else {
-            info!("Failed to update clock");
+        // 如果当前时钟频率已经是目标频率且时序未变，则直接返回
+        let clock_stale = self.clock_stale.swap(false, Ordering::Relaxed);
+        if !clock_stale && host.curr_clock_freq.load(Ordering::Relaxed) == target_clock {
+            return target_clock;
+        }
+        // 尝试设置时钟频率, 实际频率可能低于目标频率
+        match self.hc.lock().clk_freq_set(target_clock) {
+            Ok(actual_clock) => {
+                info!("BUS CLOCK: {}, target {}", actual_clock, target_clock);
+                // 更新实例的时钟频率
+                host.curr_clock_freq.store(actual_clock, Ordering::Relaxed);
+            }
+            Err(_) => {
+                info!("Failed to update clock");
+                self.clock_stale.store(clock_stale, Ordering::Relaxed);
+            }
         }
 
-        host.curr_clock_freq.get()
+        host.curr_clock_freq.load(Ordering::Relaxed)
     }
 
     fn force_clock_on(&self, enable: bool) {
-        self.hc.borrow().clock_set(enable);
+        self.hc.lock().clock_set(enable);
     }
 
     pub fn card_is_busy(&self) -> bool {
-        self.hc.borrow().check_if_card_busy()
+        self.hc.lock().check_if_card_busy()
+    }
+
+    pub fn card_is_write_protected(&self) -> bool {
+        self.hc.lock().check_if_card_write_protected()
     }
 
     fn pre_command(&self, content: &mut MCIHostTransfer, host: &MCIHost) -> MCIHostStatus {
@@ -340,8 +407,10 @@ impl SDIFDev {
             None => return Ok(()),
         };
 
-        if cmd.index() == MCIHostCommonCmd::ReadMultipleBlock as u32
-            || cmd.index() == MCIHostCommonCmd::WriteMultipleBlock as u32
+        /* 卡支持 CMD23 时预先设置块数, 否则由控制器在数据结束后自动发送 CMD12 */
+        if data.enable_auto_command23()
+            && (cmd.index() == MCIHostCommonCmd::ReadMultipleBlock as u32
+                || cmd.index() == MCIHostCommonCmd::WriteMultipleBlock as u32)
         {
             let block_count = data.block_count();
 
@@ -352,7 +421,7 @@ impl SDIFDev {
         Ok(())
     }
 
-    fn covert_command_info(&self, in_trans: &mut MCIHostTransfer) -> MCICmdData {
+    fn covert_command_info(&self, in_trans: &mut MCIHostTransfer) -> MCIHostStatus<MCICmdData> {
         let in_cmd = match in_trans.cmd() {
             Some(cmd) => cmd,
             None => panic!("Not Inited intrans"),
@@ -362,7 +431,9 @@ impl SDIFDev {
         let arg: u32 = in_cmd.argument();
         let mut flag = MCICmdFlag::empty();
 
-        if index == MCIHostCommonCmd::GoIdleState as u32 {
+        if self.init_clock_pending.swap(false, Ordering::Relaxed)
+            || index == MCIHostCommonCmd::GoIdleState as u32
+        {
             flag |= MCICmdFlag::NEED_INIT;
         }
 
@@ -398,34 +469,44 @@ impl SDIFDev {
             let mut out_data = MCIData::new();
 
             flag |= MCICmdFlag::EXP_DATA;
+            if in_data.enable_auto_command12() {
+                flag |= MCICmdFlag::NEED_AUTO_STOP;
+            }
+
+            out_data.blksz_set(in_data.block_size() as u32);
+            out_data.blkcnt_set(in_data.block_count());
+            out_data.datalen_set(in_data.block_size() as u32 * in_data.block_count());
+            out_data.timeout_set(in_data.timeout());
+
+            /* scatter/gather segments are bus addresses, cache maintenance is done by caller */
+            if in_data.is_sg() {
+                if let Some(rx_sg) = in_data.rx_sg() {
+                    flag |= MCICmdFlag::READ_DATA;
+                    out_data.sg_set(Some(rx_sg.clone()));
+                } else {
+                    flag |= MCICmdFlag::WRITE_DATA;
+                    out_data.sg_set(in_data.tx_sg().cloned());
+                }
+                return Ok(self.command_info_build(index, arg, flag, Some(out_data)));
+            }
 
-            let buf = if let Some(rx_data) = in_data.rx_data_mut() {
+            let buf = if let Some(rx_data) = in_data.rx_data_take() {
                 // Handle receive data
                 flag |= MCICmdFlag::READ_DATA;
-                take(rx_data)
-            } else if let Some(tx_data) = in_data.tx_data_mut() {
+                rx_data
+            } else if let Some(tx_data) = in_data.tx_data_take() {
                 // Handle transmit data
                 flag |= MCICmdFlag::WRITE_DATA;
-                take(tx_data)
+                tx_data
             } else {
-                // Neither rx_data nor tx_data is available
-                panic!("Transaction data initialized but contains neither rx_data nor tx_data");
+                error!("Transaction data initialized but contains neither rx_data nor tx_data");
+                return Err(MCIHostError::InvalidArgument);
             };
 
-            out_data.blksz_set(in_data.block_size() as u32);
-            out_data.blkcnt_set(in_data.block_count());
-            out_data.datalen_set(in_data.block_size() as u32 * in_data.block_count());
-
-            let bus_addr = mmap(NonNull::new(buf.as_ptr() as *mut u8).unwrap().into());
-            out_data.buf_dma_set(bus_addr as usize);
-            flush(
-                NonNull::new(buf.as_ptr() as *mut u8).unwrap(),
-                buf.len() * size_of::<u32>(),
-            );
             debug!(
                 "in covert command info, buf va {:p}, pa {:x}",
                 buf.as_ptr(),
-                bus_addr
+                buf.bus_addr()
             );
             out_data.buf_set(Some(buf));
 
@@ -434,6 +515,27 @@ impl SDIFDev {
             None
         };
 
+        Ok(self.command_info_build(index, arg, flag, out_data))
+    }
+
+    /// 内部数据缓冲区对应的 DMA 区域, 分散/聚集段由调用者维护
+    fn data_dma_region(cmd_data: &MCICmdData) -> Option<(DmaRegion, DmaDirection)> {
+        let buf = cmd_data.get_data()?.buf()?;
+        let dir = if cmd_data.flag().contains(MCICmdFlag::READ_DATA) {
+            DmaDirection::FromDevice
+        } else {
+            DmaDirection::ToDevice
+        };
+        Some((buf.region(), dir))
+    }
+
+    fn command_info_build(
+        &self,
+        index: u32,
+        arg: u32,
+        flag: MCICmdFlag,
+        out_data: Option<MCIData>,
+    ) -> MCICmdData {
         let mut out_trans = MCICmdData::new();
 
         out_trans.cmdidx_set(index);
@@ -453,72 +555,279 @@ impl SDIFDev {
         content: &mut MCIHostTransfer,
         host: &MCIHost,
     ) -> MCIHostStatus {
+        let _cmd_guard = self.cmd_lock()?;
+        if self.pending.lock().is_some() {
+            error!("non-blocking transfer in progress");
+            return Err(MCIHostError::Busy);
+        }
+
+        /* CMD23 和数据命令在同一次加锁内发出, 中间不会插入其他命令 */
         self.pre_command(content, host)?;
-        let mut cmd_data = self.covert_command_info(content);
-        let is_read_transfer = cmd_data.flag().contains(MCICmdFlag::READ_DATA);
-        let is_write_transfer = cmd_data.flag().contains(MCICmdFlag::WRITE_DATA);
+        self.transfer_locked(content, host)
+    }
 
-        let transfer_type = match (is_read_transfer, is_write_transfer) {
-            (true, false) => "READ",
-            (false, true) => "WRITE",
-            (true, true) => "READ_WRITE",
-            (false, false) => "NO_DATA",
-        };
+    /// 发出命令并等待完成, 调用者需持有命令锁
+    pub(crate) fn transfer_locked(
+        &self,
+        content: &mut MCIHostTransfer,
+        host: &MCIHost,
+    ) -> MCIHostStatus {
+        #[cfg(feature = "fault-inject")]
+        self.fault_take(content)?;
+        let cmd_data = self.transfer_start(&mut self.hc.lock(), content, host)?;
+        /* 等待期间只持有命令锁, 其他路径仍可访问控制器 */
+        if let Err(err) = self.transfer_wait(&cmd_data, host) {
+            self.trace_complete(&self.hc.lock(), &cmd_data, Err(err));
+            return Err(err);
+        }
 
-        if host.config.enable_dma {
-            if let Err(_) = self.hc.borrow_mut().dma_transfer(&mut cmd_data) {
-                return Err(MCIHostError::NoData);
+        self.transfer_finish(&mut self.hc.lock(), cmd_data, content, host)
+    }
+
+    /// 占用命令锁直到返回的 guard 被释放
+    fn cmd_lock(&self) -> MCIHostStatus<OsaMutexGuard<'_>> {
+        osa_mutex_lock(self.cmd_lock.as_ref(), Duration::MAX).map_err(|err| {
+            error!("wait command lock failed {:?}", err);
+            MCIHostError::Busy
+        })
+    }
+
+    /// 阻塞等待命令和数据完成
+    #[cfg_attr(feature = "irq", allow(unused_variables))]
+    fn transfer_wait(&self, cmd_data: &MCICmdData, host: &MCIHost) -> MCIHostStatus {
+        /* 轮询模式需要一直读写控制器寄存器 */
+        #[cfg(feature = "poll")]
+        {
+            let mut hc = self.hc.lock();
+            if host.config.enable_dma {
+                hc.poll_wait_dma_end(cmd_data)?;
+            } else {
+                hc.poll_wait_pio_end(cmd_data)?;
             }
-            #[cfg(feature = "poll")]
-            if let Err(_) = self.hc.borrow_mut().poll_wait_dma_end(&mut cmd_data) {
-                return Err(MCIHostError::NoData);
+        }
+
+        #[cfg(feature = "irq")]
+        {
+            use crate::osa::consts::{
+                FSDIF_TRANS_ERR_EVENTS, SDMMC_OSA_EVENT_FLAG_OR,
+                SDMMC_OSA_EVENT_TRANSFER_CMD_SUCCESS, SDMMC_OSA_EVENT_TRANSFER_DATA_SUCCESS,
+            };
+
+            let irq_events = self.irq.events();
+            let complete_events = if cmd_data.get_data().is_some() {
+                SDMMC_OSA_EVENT_TRANSFER_CMD_SUCCESS | SDMMC_OSA_EVENT_TRANSFER_DATA_SUCCESS
+            } else {
+                SDMMC_OSA_EVENT_TRANSFER_CMD_SUCCESS
+            };
+
+            /* 逐个等待完成事件, 任一错误事件发生时立即返回 */
+            let deadline = Deadline::after(cmd_data.timeout());
+            let mut pending = complete_events;
+            while pending != 0 {
+                let events = match irq_events.wait(
+                    pending | FSDIF_TRANS_ERR_EVENTS,
+                    SDMMC_OSA_EVENT_FLAG_OR,
+                    deadline.remaining(),
+                ) {
+                    Ok(events) => events,
+                    Err(_) => {
+                        error!("wait command done timeout!");
+                        self.hc.lock().register_dump();
+                        irq_events.clear(complete_events);
+                        return Err(MCIHostError::Timeout);
+                    }
+                };
+
+                if events & FSDIF_TRANS_ERR_EVENTS != 0 {
+                    error!("transfer failed, events 0x{:x}", events);
+                    self.hc.lock().register_dump();
+                    irq_events.clear(complete_events | FSDIF_TRANS_ERR_EVENTS);
+                    return Err(self.irq_error_take());
+                }
+                pending &= !events;
             }
-        } else {
-            if let Err(_) = self.hc.borrow_mut().pio_transfer(&mut cmd_data) {
-                return Err(MCIHostError::NoData);
+
+            irq_events.clear(complete_events);
+        }
+
+        Ok(())
+    }
+
+    /// Issue the transfer and return at once, finish it by [`SDIFDev::transfer_poll`]
+    pub fn transfer_start_nb(
+        &self,
+        content: &mut MCIHostTransfer,
+        host: &MCIHost,
+    ) -> MCIHostStatus {
+        let _cmd_guard = self.cmd_lock()?;
+        if self.pending.lock().is_some() {
+            error!("non-blocking transfer in progress");
+            return Err(MCIHostError::Busy);
+        }
+
+        /* CMD23 阻塞发出, 数据命令在同一次加锁内启动 */
+        self.pre_command(content, host)?;
+        let cmd_data = self.transfer_start(&mut self.hc.lock(), content, host)?;
+        *self.pending.lock() = Some(SDIFPending {
+            deadline: Deadline::after(cmd_data.timeout()),
+            cmd_data,
+        });
+        Ok(())
+    }
+
+    /// Check the transfer issued by [`SDIFDev::transfer_start_nb`], response and data
+    /// are filled into `content` once it returns `Ok`
+    pub fn transfer_poll(
+        &self,
+        content: &mut MCIHostTransfer,
+        host: &MCIHost,
+    ) -> nb::Result<(), MCIHostError> {
+        let _cmd_guard = self.cmd_lock()?;
+        let mut hc = self.hc.lock();
+        let mut pending = self.pending.lock();
+        let trans = pending.as_ref().ok_or(MCIHostError::NoTransferInProgress)?;
+
+        let result = match self.transfer_done(&mut hc, &trans.cmd_data) {
+            Err(nb::Error::WouldBlock) if !trans.deadline.is_expired() => {
+                return Err(nb::Error::WouldBlock);
             }
-            #[cfg(feature = "poll")]
-            if let Err(_) = self.hc.borrow_mut().poll_wait_pio_end(&mut cmd_data) {
-                return Err(MCIHostError::NoData);
+            Err(nb::Error::WouldBlock) => {
+                error!("wait command done timeout!");
+                hc.register_dump();
+                Err(MCIHostError::Timeout)
             }
+            Err(nb::Error::Other(err)) => Err(err),
+            Ok(()) => Ok(()),
+        };
+
+        let trans = pending.take().unwrap();
+        if let Err(err) = result {
+            self.trace_complete(&hc, &trans.cmd_data, Err(err));
+            return Err(nb::Error::Other(err));
         }
+        Ok(self.transfer_finish(&mut hc, trans.cmd_data, content, host)?)
+    }
+
+    /// 检查一次传输是否完成, 中断模式下由中断处理函数记录事件
+    fn transfer_done(&self, hc: &mut MCI, cmd_data: &MCICmdData) -> nb::Result<(), MCIHostError> {
+        #[cfg(feature = "poll")]
+        return hc.poll(cmd_data).map_err(|err| err.map(MCIHostError::from));
 
         #[cfg(feature = "irq")]
         {
-            use crate::osa::{osa_event_clear, osa_event_wait};
-            use crate::{
-                mci::regs::MCIRawInts,
-                osa::consts::{
-                    SDMMC_OSA_EVENT_TRANSFER_CMD_SUCCESS, SDMMC_OSA_EVENT_TRANSFER_DATA_SUCCESS,
-                },
+            use crate::osa::consts::{
+                FSDIF_TRANS_ERR_EVENTS, SDMMC_OSA_EVENT_TRANSFER_CMD_SUCCESS,
+                SDMMC_OSA_EVENT_TRANSFER_DATA_SUCCESS,
             };
 
+            let irq_events = self.irq.events();
             let complete_events = if cmd_data.get_data().is_some() {
                 SDMMC_OSA_EVENT_TRANSFER_CMD_SUCCESS | SDMMC_OSA_EVENT_TRANSFER_DATA_SUCCESS
             } else {
                 SDMMC_OSA_EVENT_TRANSFER_CMD_SUCCESS
             };
 
-            if osa_event_wait(complete_events, 500).is_err() {
-                error!("wait command done timeout!");
-                self.hc.borrow().register_dump();
-                return Err(MCIHostError::Timeout);
+            let events = irq_events.get();
+            if events & FSDIF_TRANS_ERR_EVENTS != 0 {
+                error!("transfer failed, events 0x{:x}", events);
+                hc.register_dump();
+                irq_events.clear(complete_events | FSDIF_TRANS_ERR_EVENTS);
+                return Err(nb::Error::Other(self.irq_error_take()));
+            }
+            if events & complete_events != complete_events {
+                return Err(nb::Error::WouldBlock);
             }
 
-            osa_event_clear(complete_events);
+            irq_events.clear(complete_events);
+            Ok(())
         }
+    }
+
+    /// 中断处理函数记录的错误, 没有记录时按传输失败处理
+    #[cfg(feature = "irq")]
+    fn irq_error_take(&self) -> MCIHostError {
+        self.irq
+            .error_take()
+            .map_or(MCIHostError::TransferFailed, MCIHostError::from)
+    }
+
+    /// 转换命令并发出, 不等待完成
+    fn transfer_start(
+        &self,
+        hc: &mut MCI,
+        content: &mut MCIHostTransfer,
+        host: &MCIHost,
+    ) -> MCIHostStatus<MCICmdData> {
+        if !host.config.enable_dma && content.data().is_some_and(|data| data.is_sg()) {
+            error!("scatter/gather transfer needs DMA mode");
+            return Err(MCIHostError::HostNotSupport);
+        }
+
+        let mut cmd_data = self.covert_command_info(content)?;
+        let is_read_transfer = cmd_data.flag().contains(MCICmdFlag::READ_DATA);
+        let is_write_transfer = cmd_data.flag().contains(MCICmdFlag::WRITE_DATA);
 
-        if let Err(_) = self.hc.borrow_mut().cmd_response_get(&mut cmd_data) {
+        /* PIO 模式下由 CPU 搬运数据, 不需要维护 cache */
+        if host.config.enable_dma {
+            if let Some((region, dir)) = Self::data_dma_region(&cmd_data) {
+                region.prepare(dir);
+            }
+        }
+
+        let transfer_type = match (is_read_transfer, is_write_transfer) {
+            (true, false) => "READ",
+            (false, true) => "WRITE",
+            (true, true) => "READ_WRITE",
+            (false, false) => "NO_DATA",
+        };
+
+        /* 清除上次传输残留的事件 */
+        #[cfg(feature = "irq")]
+        self.irq.events().clear(
+            crate::osa::consts::SDMMC_OSA_EVENT_TRANSFER_CMD_SUCCESS
+                | crate::osa::consts::SDMMC_OSA_EVENT_TRANSFER_DATA_SUCCESS
+                | crate::osa::consts::FSDIF_TRANS_ERR_EVENTS,
+        );
+
+        self.irq.cmd_start(&cmd_data);
+        self.trace_issue(&cmd_data);
+
+        let started = if host.config.enable_dma {
+            hc.dma_transfer(&mut cmd_data)
+        } else {
+            hc.pio_transfer(&mut cmd_data)
+        };
+        if let Err(err) = started {
+            let err = MCIHostError::from(err);
+            self.trace_complete(hc, &cmd_data, Err(err));
+            return Err(err);
+        }
+
+        Ok(cmd_data)
+    }
+
+    /// 取回响应和数据, 写回 `content`
+    fn transfer_finish(
+        &self,
+        hc: &mut MCI,
+        mut cmd_data: MCICmdData,
+        content: &mut MCIHostTransfer,
+        host: &MCIHost,
+    ) -> MCIHostStatus {
+        if let Err(_) = hc.cmd_response_get(&mut cmd_data) {
             info!("Transfer cmd and data failed !!!");
+            self.trace_complete(hc, &cmd_data, Err(MCIHostError::Timeout));
             return Err(MCIHostError::Timeout);
         }
+        self.trace_complete(hc, &cmd_data, Ok(()));
 
-        if let Some(_) = content.data() {
-            let data = cmd_data.get_mut_data().unwrap();
-            invalidate(
-                NonNull::new(data.buf().unwrap().as_ptr() as *mut u8).unwrap(),
-                data.buf().unwrap().len() * 4,
-            );
+        if host.config.enable_dma {
+            if let Some((region, dir)) = Self::data_dma_region(&cmd_data) {
+                region.complete(dir);
+            }
+        }
+
+        if let Some(data) = cmd_data.get_mut_data() {
             if let Some(rx_data) = data.buf_take() {
                 if let Some(in_data) = content.data_mut() {
                     in_data.rx_data_set(Some(rx_data));
@@ -535,4 +844,146 @@ impl SDIFDev {
 
         Ok(())
     }
+
+    pub fn observer_set(&self, observer: Option<Arc<dyn MCIHostObserver>>) {
+        *self.observer.lock() = observer;
+    }
+
+    /// 设置环形缓冲区, `None` 关闭记录
+    pub fn trace_ring_set(&self, ring: Option<MCIHostTraceRing>) {
+        *self.trace_ring.lock() = ring;
+    }
+
+    pub fn trace_events(&self) -> Vec<MCIHostTraceEvent> {
+        self.trace_ring
+            .lock()
+            .as_ref()
+            .map_or(Vec::new(), |ring| ring.events())
+    }
+
+    pub fn trace_dump(&self) {
+        match self.trace_ring.lock().as_ref() {
+            Some(ring) => ring.dump(),
+            None => warn!("command trace is not enabled"),
+        }
+    }
+
+    pub fn register_snapshot(&self) -> MCIRegisterSnapshot {
+        self.hc.lock().register_snapshot()
+    }
+
+    pub fn error_registers(&self) -> Option<MCIRegisterSnapshot> {
+        *self.error_regs.lock()
+    }
+
+    pub fn cmd_stats(&self) -> MCIHostCmdStats {
+        *self.stats.lock()
+    }
+
+    pub fn cmd_stats_reset(&self) {
+        *self.stats.lock() = MCIHostCmdStats::default();
+    }
+
+    /// Make the next `count` data transfers fail with `err` before they are issued
+    #[cfg(feature = "fault-inject")]
+    pub fn fault_inject_set(&self, err: MCIHostError, count: u32) {
+        *self.fault.lock() = (count != 0).then_some((err, count));
+    }
+
+    /// 有注入的故障时, 数据传输不发出直接失败
+    #[cfg(feature = "fault-inject")]
+    fn fault_take(&self, content: &MCIHostTransfer) -> MCIHostStatus {
+        if content.data().is_none() {
+            return Ok(());
+        }
+
+        let mut fault = self.fault.lock();
+        let Some((err, count)) = *fault else {
+            return Ok(());
+        };
+        *fault = (count > 1).then_some((err, count - 1));
+        warn!("inject fault {:?}, {} left", err, count - 1);
+        Err(err)
+    }
+
+    fn trace_enabled(&self) -> bool {
+        self.observer.lock().is_some() || self.trace_ring.lock().is_some()
+    }
+
+    fn trace_event(&self, event: MCIHostTraceEvent) {
+        if let Some(ring) = self.trace_ring.lock().as_mut() {
+            ring.push(event);
+        }
+        /* 先释放锁再回调, 允许观察者在回调中更换自己 */
+        let observer = self.observer.lock().clone();
+        if let Some(observer) = observer {
+            match event.phase {
+                MCIHostTracePhase::Issue => observer.cmd_issue(&event),
+                MCIHostTracePhase::Complete => observer.cmd_complete(&event),
+            }
+        }
+    }
+
+    fn trace_data_len(cmd_data: &MCICmdData) -> u32 {
+        cmd_data.get_data().map_or(0, |data| data.datalen())
+    }
+
+    fn trace_issue(&self, cmd_data: &MCICmdData) {
+        let time = now();
+        *self.issue_time.lock() = time;
+        self.stats.lock().issue_record();
+        if !self.trace_enabled() {
+            return;
+        }
+        self.trace_event(MCIHostTraceEvent {
+            phase: MCIHostTracePhase::Issue,
+            index: cmd_data.cmdidx(),
+            argument: cmd_data.cmdarg(),
+            flags: cmd_data.flag().bits(),
+            response: [0; 4],
+            raw_ints: 0,
+            data_len: Self::trace_data_len(cmd_data),
+            time,
+            duration: Duration::ZERO,
+            error: None,
+            regs: None,
+        });
+    }
+
+    fn trace_complete(&self, hc: &MCI, cmd_data: &MCICmdData, result: MCIHostStatus) {
+        let time = now();
+        let duration = time.saturating_sub(*self.issue_time.lock());
+        self.stats
+            .lock()
+            .complete_record(cmd_data.get_data().is_some(), duration, result.err());
+        /* 在复位控制器之前保存失败现场 */
+        let regs = result.is_err().then(|| hc.register_snapshot());
+        if regs.is_some() {
+            *self.error_regs.lock() = regs;
+        }
+        if !self.trace_enabled() {
+            return;
+        }
+        let mut response = [0; 4];
+        response.copy_from_slice(&cmd_data.get_response()[..4]);
+        /* 中断模式下原始状态已被中断处理函数清除, 取其记录的值 */
+        let raw_ints = if cfg!(feature = "irq") {
+            self.irq.ints_seen()
+        } else {
+            hc.raw_ints_seen()
+        };
+        self.trace_event(MCIHostTraceEvent {
+            phase: MCIHostTracePhase::Complete,
+            index: cmd_data.cmdidx(),
+            argument: cmd_data.cmdarg(),
+            flags: cmd_data.flag().bits(),
+            response,
+            raw_ints,
+            data_len: Self::trace_data_len(cmd_data),
+            time,
+            duration,
+            error: result.err(),
+            regs,
+        });
+    }
 }
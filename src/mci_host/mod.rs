@@ -4,18 +4,26 @@ mod err;
 mod mci_card_base;
 mod mci_host_card_detect;
 mod mci_host_config;
+mod mci_host_stats;
+mod mci_host_trace;
 mod mci_host_transfer;
 pub mod mci_sdif;
 pub mod sd;
 
-use core::{cell::Cell, ptr::NonNull};
+use core::ptr::NonNull;
+use core::sync::atomic::AtomicU32;
 
-use alloc::{boxed::Box, rc::Rc};
+use alloc::{boxed::Box, sync::Arc};
+use spin::Mutex;
 
 use constants::*;
-use err::{MCIHostError, MCIHostStatus};
+pub use constants::{MCIHostCardStatusFlag, MCIHostOperationVoltage, MCIHostResponseType};
+pub use err::{MCIHostError, MCIHostStatus};
 use mci_host_card_detect::MCIHostCardDetect;
 use mci_host_config::MCIHostConfig;
+pub use mci_host_config::MCIHostRecoveryPolicy;
+pub use mci_host_stats::{MCIHostCmdStats, MCIHostLatencyHistogram, MCI_HOST_LATENCY_BUCKETS};
+pub use mci_host_trace::{MCIHostObserver, MCIHostTraceEvent, MCIHostTracePhase, MCIHostTraceRing};
 use mci_host_transfer::{MCIHostCmd, MCIHostTransfer};
 use mci_sdif::sdif_device::SDIFDev;
 
@@ -25,17 +33,17 @@ type MCIHostCardIntFn = fn();
 pub struct MCIHost {
     pub(crate) dev: Box<SDIFDev>,
     pub(crate) config: MCIHostConfig,
-    pub(crate) curr_voltage: Cell<MCIHostOperationVoltage>,
+    pub(crate) curr_voltage: Mutex<MCIHostOperationVoltage>,
     pub(crate) curr_bus_width: u32,
-    pub(crate) curr_clock_freq: Cell<u32>,
+    pub(crate) curr_clock_freq: AtomicU32,
 
     pub(crate) source_clock_hz: u32,
     pub(crate) capability: MCIHostCapability,
-    pub(crate) max_block_count: Cell<u32>,
+    pub(crate) max_block_count: AtomicU32,
     pub(crate) max_block_size: u32,
     pub(crate) tuning_type: u8,
 
-    pub(crate) cd: Option<Rc<MCIHostCardDetect>>, // 卡检测
+    pub(crate) cd: Option<Arc<MCIHostCardDetect>>, // 卡检测
     pub(crate) card_int: MCIHostCardIntFn,
     //todo uint8_t tuningType 没有移植
 }
@@ -46,12 +54,12 @@ impl MCIHost {
         MCIHost {
             dev,
             config,
-            curr_voltage: Cell::new(MCIHostOperationVoltage::None),
+            curr_voltage: Mutex::new(MCIHostOperationVoltage::None),
             curr_bus_width: 0,
-            curr_clock_freq: Cell::new(0),
+            curr_clock_freq: AtomicU32::new(0),
             source_clock_hz: 0,
             capability: MCIHostCapability::empty(),
-            max_block_count: Cell::new(0),
+            max_block_count: AtomicU32::new(0),
             max_block_size: 0,
             tuning_type: 0,
             cd: None,
@@ -122,7 +130,8 @@ impl MCIHost {
         let mut content = MCIHostTransfer::new();
         content.set_cmd(Some(command));
 
-        let err = self.dev.transfer_function(&mut content, self);
+        /* 由 pre_command 在持有命令锁时调用, 不能再次加锁 */
+        let err = self.dev.transfer_locked(&mut content, self);
 
         let command = content.cmd().unwrap();
         let response = command.response();
@@ -1,4 +1,5 @@
 use bitflags::bitflags;
+use core::time::Duration;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum SdTimingMode {
@@ -9,16 +10,18 @@ pub(crate) enum SdTimingMode {
     DDR50Mode = 4,
 }
 
+/// UHS-I 输出驱动类型, 值为 CMD6 功能组 3 的功能号
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub(crate) enum SdDriverStrength {
+pub enum SdDriverStrength {
     TypeB = 0,
     TypeA = 1,
     TypeC = 2,
     TypeD = 3,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub(crate) enum SdMaxCurrent {
+/// UHS-I 电流限制, 值为 CMD6 功能组 4 的功能号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SdMaxCurrent {
     Limit200mA = 0,
     Limit400mA = 1,
     Limit600mA = 2,
@@ -109,6 +112,8 @@ pub(crate) enum SdSpecificationVersion {
 
 pub(crate) const SD_POWER_ON_DELAY_MS: u32 = 400;
 pub(crate) const SD_POWER_OFF_DELAY_MS: u32 = 100;
+/// 切换信号电压时停止时钟的时间, 规范要求至少 5ms
+pub(crate) const SD_VOLTAGE_SWITCH_CLOCK_GATE: Duration = Duration::from_millis(5);
 
 pub(crate) const SD_CLOCK_25MHZ: u32 = 25_000_000;
 pub(crate) const SD_CLOCK_50MHZ: u32 = 50_000_000;
@@ -120,4 +125,39 @@ pub(crate) const SD_PRODUCT_NAME_BYTES: usize = 5;
 pub(crate) const SD_MAX_RW_BLK: usize = 1024;
 pub(crate) const SD_BLOCK_SIZE: usize = 512;
 
-pub(crate) const SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT: u32 = 600;
+pub(crate) const SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT: Duration = Duration::from_millis(600);
+pub(crate) const SD_CARD_DETECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/* SD 规范规定的超时上限 */
+pub(crate) const SD_READ_TIMEOUT: Duration = Duration::from_millis(100);
+pub(crate) const SD_WRITE_TIMEOUT: Duration = Duration::from_millis(250);
+pub(crate) const SDXC_WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+pub(crate) const SD_INIT_TIMEOUT: Duration = Duration::from_secs(1);
+/* ACMD41 轮询间隔 */
+pub(crate) const SD_OPERATION_CONDITION_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+bitflags! {
+    /// CMD42 lock card data structure mode bits
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) struct SdLockUnlockFlag: u8 {
+        const SET_PWD     = 1 << 0;  // Set new password
+        const CLR_PWD     = 1 << 1;  // Clear password
+        const LOCK_UNLOCK = 1 << 2;  // Lock the card, unlock if cleared
+        const ERASE       = 1 << 3;  // Force erase
+    }
+}
+
+pub(crate) const SD_PASSWORD_MAX_LEN: usize = 16;
+/* 根据规范, 强制擦除最长耗时 3 分钟 */
+pub(crate) const SD_FORCE_ERASE_TIMEOUT: Duration = Duration::from_secs(3 * 60);
+
+/// CMD20 参数 [31:28], 速度等级控制操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SdSpeedClassCtrl {
+    StartRecording = 0,
+    CreateDir = 1,
+    UpdateDir = 2,
+}
+
+/* 根据规范, CMD20 的忙等待最长 1 秒 */
+pub(crate) const SD_SPEED_CLASS_CONTROL_TIMEOUT: Duration = Duration::from_secs(1);
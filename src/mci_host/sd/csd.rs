@@ -1,4 +1,12 @@
 use bitflags::bitflags;
+use core::time::Duration;
+
+use super::consts::{SD_READ_TIMEOUT, SD_WRITE_TIMEOUT};
+
+/* TAAC time value x10, index by bits [6:3] */
+const SD_TAAC_VALUE: [u64; 16] = [
+    0, 10, 12, 13, 15, 20, 25, 30, 35, 40, 45, 50, 55, 60, 70, 80,
+];
 
 #[derive(Debug, Default)]
 pub struct SdCsd {
@@ -45,6 +53,37 @@ impl SdCsd {
             file_format: 0,
         }
     }
+
+    /// Typical read access time, TAAC + NSAC * 100 clock cycles
+    fn access_time(&self, clock_hz: u32) -> Duration {
+        let unit_ns = 10u64.pow((self.data_read_access_time1 & 0x7) as u32);
+        let taac_ns =
+            unit_ns * SD_TAAC_VALUE[((self.data_read_access_time1 >> 3) & 0xF) as usize] / 10;
+        let nsac_ns = if clock_hz == 0 {
+            0
+        } else {
+            self.data_read_access_time2 as u64 * 100 * 1_000_000_000 / clock_hz as u64
+        };
+        Duration::from_nanos(taac_ns + nsac_ns)
+    }
+
+    /// Read timeout of one block, 100 times the typical access time but no more than 100ms.
+    /// CSD 2.0 cards use fixed value
+    pub fn read_timeout(&self, clock_hz: u32) -> Duration {
+        if self.csd_structure != 0 {
+            return SD_READ_TIMEOUT;
+        }
+        (self.access_time(clock_hz) * 100).min(SD_READ_TIMEOUT)
+    }
+
+    /// Write timeout of one block, read timeout scaled by R2W_FACTOR but no more than 250ms.
+    /// CSD 2.0 cards use fixed value
+    pub fn write_timeout(&self, clock_hz: u32) -> Duration {
+        if self.csd_structure != 0 {
+            return SD_WRITE_TIMEOUT;
+        }
+        (self.access_time(clock_hz) * 100 * (1 << self.write_speed_factor)).min(SD_WRITE_TIMEOUT)
+    }
 }
 
 bitflags! {
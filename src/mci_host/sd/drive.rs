@@ -0,0 +1,144 @@
+//! UHS-I 驱动强度和电流限制, 通过 CMD6 功能组 3 和 4 与卡协商
+use log::*;
+
+use super::consts::*;
+use super::SdCard;
+use crate::mci_host::constants::*;
+use crate::mci_host::err::{MCIHostError, MCIHostStatus};
+
+impl SdDriverStrength {
+    fn from_func(func: u32) -> Self {
+        match func {
+            1 => SdDriverStrength::TypeA,
+            2 => SdDriverStrength::TypeC,
+            3 => SdDriverStrength::TypeD,
+            _ => SdDriverStrength::TypeB,
+        }
+    }
+}
+
+impl SdMaxCurrent {
+    fn from_func(func: u32) -> Self {
+        match func {
+            1 => SdMaxCurrent::Limit400mA,
+            2 => SdMaxCurrent::Limit600mA,
+            3 => SdMaxCurrent::Limit800mA,
+            _ => SdMaxCurrent::Limit200mA,
+        }
+    }
+}
+
+impl SdCard {
+    /// Output driver type asked from the card in UHS-I modes, `None` keeps the default
+    /// type B, call it before [`SdCard::init`]
+    pub fn driver_strength_set(&mut self, driver_strength: Option<SdDriverStrength>) {
+        self.usr_param.driver_strength = driver_strength;
+    }
+
+    /// Upper bound of the current the card may draw, e.g. limited by the board power
+    /// supply, call it before [`SdCard::init`]
+    pub fn current_limit_set(&mut self, max_current: SdMaxCurrent) {
+        self.usr_param.max_current = max_current;
+    }
+
+    pub fn driver_strength(&self) -> SdDriverStrength {
+        self.driver_strength
+    }
+
+    pub fn max_current(&self) -> SdMaxCurrent {
+        self.max_current
+    }
+
+    /// 按将要切换的时序协商驱动强度和电流限制, 规范要求在 CMD6 切换时序之前调用
+    pub(crate) fn uhs_drive_select(&mut self, timing: SdTimingMode) -> MCIHostStatus {
+        let uhs_timing = matches!(
+            timing,
+            SdTimingMode::SDR50Mode | SdTimingMode::SDR104Mode | SdTimingMode::DDR50Mode
+        );
+        if uhs_timing {
+            self.driver_strength_select()?;
+        }
+        self.current_limit_select(uhs_timing)
+    }
+
+    /// CMD6 功能组 3, 卡和主机都支持时才使用用户指定的驱动类型, 切换失败时保持
+    /// 原驱动类型继续初始化
+    fn driver_strength_select(&mut self) -> MCIHostStatus {
+        let Some(wanted) = self.usr_param.driver_strength else {
+            return Ok(());
+        };
+        if wanted == self.driver_strength {
+            return Ok(());
+        }
+
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        /* 主机侧总是支持 type B, type C 由能力位声明 */
+        let host_support = match wanted {
+            SdDriverStrength::TypeB => true,
+            SdDriverStrength::TypeC => host.capability.contains(MCIHostCapability::DRIVER_TYPE_C),
+            _ => false,
+        };
+        let card_support = match self.func_check(SdGroupNum::DriverStrength, 0xf) {
+            Ok((card_support, _)) => card_support,
+            Err(err) => {
+                warn!(
+                    "check driver strength failed {:?}, keep {:?}",
+                    err, self.driver_strength
+                );
+                return Ok(());
+            }
+        };
+        if !host_support || card_support & (1 << wanted as u32) == 0 {
+            warn!(
+                "driver type {:?} not supported by host or card (0x{:x}), keep {:?}",
+                wanted, card_support, self.driver_strength
+            );
+            return Ok(());
+        }
+
+        if let Err(err) = self.func_select(SdGroupNum::DriverStrength, wanted as u32) {
+            warn!(
+                "switch driver strength to {:?} failed {:?}, keep {:?}",
+                wanted, err, self.driver_strength
+            );
+            return Ok(());
+        }
+        self.driver_strength = wanted;
+        info!("driver strength {:?}", wanted);
+        Ok(())
+    }
+
+    /// CMD6 功能组 4, 只有 SDR50, SDR104 和 DDR50 可以超过 200mA
+    fn current_limit_select(&mut self, uhs_timing: bool) -> MCIHostStatus {
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        let limit = if uhs_timing && host.capability.contains(MCIHostCapability::SET_CURRENT) {
+            self.usr_param.max_current
+        } else {
+            SdMaxCurrent::Limit200mA
+        };
+        if limit == self.max_current && limit == SdMaxCurrent::Limit200mA {
+            return Ok(());
+        }
+
+        let (card_support, _) = self.func_check(SdGroupNum::CurrentLimit, 0xf)?;
+        /* 取不超过限制的最大档位, 200mA 总是支持 */
+        let target = (0..=limit as u32)
+            .rev()
+            .find(|&func| func == 0 || card_support & (1 << func) != 0)
+            .map_or(SdMaxCurrent::Limit200mA, SdMaxCurrent::from_func);
+        if target == self.max_current {
+            return Ok(());
+        }
+
+        if let Err(err) = self.func_select(SdGroupNum::CurrentLimit, target as u32) {
+            info!(
+                "\r\nError: switch current limit to {:?} failed {:?}\r\n",
+                target, err
+            );
+            return Err(MCIHostError::SetCurrentLimitFail);
+        }
+        self.max_current = target;
+        info!("current limit {:?}", target);
+        Ok(())
+    }
+}
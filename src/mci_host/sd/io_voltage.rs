@@ -1,13 +1,18 @@
+use log::*;
+
 use crate::mci_host::constants::MCIHostOperationVoltage;
+use crate::mci_host::err::{MCIHostError, MCIHostStatus};
 
-use super::consts::SdIoVoltageCtrlType;
+use super::consts::{SdCardFlag, SdIoVoltageCtrlType};
+use super::SdCard;
 
 pub(crate) struct SdIoVoltage {
     typ: SdIoVoltageCtrlType,
     func: Option<SdIoVoltageFn>,
 }
 
-type SdIoVoltageFn = fn(MCIHostOperationVoltage);
+/// Switch the board regulator of the card signal lines to the voltage, return `false` if failed
+pub type SdIoVoltageFn = fn(MCIHostOperationVoltage) -> bool;
 
 impl SdIoVoltage {
     pub(crate) fn new() -> Self {
@@ -33,3 +38,39 @@ impl SdIoVoltage {
         self.func = func;
     }
 }
+
+impl SdCard {
+    /// Switch the signaling voltage by `func` instead of the controller, `None` goes back to
+    /// the controller. Only used when the host supports 1.8V, call it before [`SdCard::init`]
+    pub fn io_voltage_func_set(&mut self, func: Option<SdIoVoltageFn>) -> MCIHostStatus {
+        let io_voltage = self
+            .usr_param
+            .io_voltage
+            .as_mut()
+            .ok_or(MCIHostError::HostNotSupport)?;
+        io_voltage.typ_set(match func {
+            Some(_) => SdIoVoltageCtrlType::ByGpio,
+            None => SdIoVoltageCtrlType::ByHost,
+        });
+        io_voltage.set_func(func);
+        Ok(())
+    }
+
+    /// CMD11 之后切换失败, 卡的状态不确定, 重新上电并回到 3.3V 默认速度
+    pub(crate) fn voltage_switch_fallback(&mut self) -> MCIHostStatus {
+        warn!("1.8V signaling switch failed, power cycle card and fall back to 3.3V");
+        if let Err(err) = self.switch_io_voltage(MCIHostOperationVoltage::Voltage330V) {
+            error!("switch signal lines back to 3.3V failed {:?}", err);
+            return Err(MCIHostError::SwitchVoltage33VFail);
+        }
+        self.card_power_set(false)?;
+        self.card_power_set(true)?;
+
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        self.base.bus_clk_hz = host.dev.card_clock_set(self.base.bus_clk_hz, host);
+        self.operation_voltage = MCIHostOperationVoltage::Voltage330V;
+        self.flags.remove(SdCardFlag::SupportVoltage180v);
+
+        Err(MCIHostError::SwitchVoltage18VFail33VSuccess)
+    }
+}
@@ -0,0 +1,193 @@
+//! SD 卡密码保护 (CMD42 LOCK_UNLOCK)
+use alloc::vec;
+use alloc::vec::Vec;
+use log::*;
+
+use super::consts::*;
+use super::csd::SdCardCmdClass;
+use super::SdCard;
+use crate::mci_host::constants::*;
+use crate::mci_host::err::{MCIHostError, MCIHostStatus};
+use crate::mci_host::mci_host_transfer::{MCIHostCmd, MCIHostData, MCIHostTransfer};
+
+impl SdCard {
+    /// Set a password on a card which has none
+    pub fn password_set(&mut self, password: &[u8]) -> MCIHostStatus {
+        self.lock_unlock(SdLockUnlockFlag::SET_PWD, &[password])
+    }
+
+    /// Replace the current password with a new one
+    pub fn password_replace(&mut self, old_password: &[u8], new_password: &[u8]) -> MCIHostStatus {
+        self.lock_unlock(SdLockUnlockFlag::SET_PWD, &[old_password, new_password])
+    }
+
+    /// Remove the password, card must be unlocked
+    pub fn password_clear(&mut self, password: &[u8]) -> MCIHostStatus {
+        self.lock_unlock(SdLockUnlockFlag::CLR_PWD, &[password])
+    }
+
+    /// Lock the card with its current password
+    pub fn lock(&mut self, password: &[u8]) -> MCIHostStatus {
+        self.lock_unlock(SdLockUnlockFlag::LOCK_UNLOCK, &[password])
+    }
+
+    /// Unlock the card and finish the init steps skipped while it was locked
+    pub fn unlock(&mut self, password: &[u8]) -> MCIHostStatus {
+        self.lock_unlock(SdLockUnlockFlag::empty(), &[password])?;
+        self.locked_init_finish()
+    }
+
+    /// Erase all user data together with the password, card must be locked
+    pub fn force_erase(&mut self) -> MCIHostStatus {
+        self.lock_unlock(SdLockUnlockFlag::ERASE, &[])?;
+        self.locked_init_finish()
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.is_locked
+    }
+
+    /// 卡在锁定状态下只响应 basic/lock card 类命令, 解锁后补做总线时序切换
+    fn locked_init_finish(&mut self) -> MCIHostStatus {
+        if !self.bus_timing_pending {
+            return Ok(());
+        }
+
+        if self.bus_timing_select().is_err() {
+            return Err(MCIHostError::SwitchBusTimingFailed);
+        }
+        self.bus_timing_pending = false;
+
+        self.card_dump();
+        Ok(())
+    }
+
+    /// CMD 42
+    fn lock_unlock(&mut self, mode: SdLockUnlockFlag, passwords: &[&[u8]]) -> MCIHostStatus {
+        if self.csd.card_command_classes & SdCardCmdClass::LockCard.bits() == 0 {
+            info!("\r\nError: current card not support CMD42\r\n");
+            return Err(MCIHostError::CardNotSupport);
+        }
+
+        if passwords
+            .iter()
+            .any(|pwd| pwd.is_empty() || pwd.len() > SD_PASSWORD_MAX_LEN)
+        {
+            error!(
+                "password length should be 1 ~ {} bytes",
+                SD_PASSWORD_MAX_LEN
+            );
+            return Err(MCIHostError::InvalidArgument);
+        }
+
+        /* lock card data structure: mode, PWDS_LEN, password data, force erase only sends mode */
+        let mut lock_data = vec![mode.bits()];
+        if !mode.contains(SdLockUnlockFlag::ERASE) {
+            lock_data.push(passwords.iter().map(|pwd| pwd.len()).sum::<usize>() as u8);
+            passwords
+                .iter()
+                .for_each(|pwd| lock_data.extend_from_slice(pwd));
+        }
+
+        if Err(MCIHostError::CardStatusIdle)
+            != self.polling_card_status_busy(SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT)
+        {
+            error!("Error: lock/unlock failed, card status busy");
+            return Err(MCIHostError::PollingCardIdleFailed);
+        }
+
+        /* DDR50 模式下块长固定为 512 字节, 锁数据结构之后补 0 */
+        if self.current_timing == SdTimingMode::DDR50Mode {
+            lock_data.resize(SD_BLOCK_SIZE, 0);
+        }
+
+        /* block length should be the size of lock card data structure */
+        if self.block_size_set(lock_data.len() as u32).is_err() {
+            return Err(MCIHostError::SetCardBlockSizeFailed);
+        }
+
+        let result = self.lock_unlock_data_send(&lock_data);
+
+        /* LOCK_UNLOCK_FAILED is reported in the status right after the data block */
+        let status = self.card_status_get();
+
+        let timeout = if mode.contains(SdLockUnlockFlag::ERASE) {
+            SD_FORCE_ERASE_TIMEOUT
+        } else {
+            SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT
+        };
+        if Err(MCIHostError::CardStatusIdle) != self.polling_card_status_busy(timeout) {
+            error!("Error: wait lock/unlock complete failed");
+            return Err(MCIHostError::WaitWriteCompleteFailed);
+        }
+
+        /* restore block length no matter CMD42 succeed or not */
+        if self.block_size_set(self.base.block_size).is_err() {
+            return Err(MCIHostError::SetCardBlockSizeFailed);
+        }
+
+        result?;
+        if status? & MCIHostCardStatusFlag::LOCK_UNLOCK_FAILED.bits() != 0 {
+            error!("Error: CMD42 mode 0x{:x} rejected by card", mode.bits());
+            return Err(MCIHostError::LockUnlockFailed);
+        }
+
+        self.is_locked =
+            self.card_status_get()? & MCIHostCardStatusFlag::CARD_IS_LOCKED.bits() != 0;
+        info!("card lock/unlock done, locked = {}", self.is_locked);
+
+        Ok(())
+    }
+
+    fn lock_unlock_data_send(&mut self, lock_data: &[u8]) -> MCIHostStatus {
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+
+        let mut command = MCIHostCmd::new();
+
+        command.index_set(MCIHostCommonCmd::LockUnlock as u32);
+        command.argument_set(0);
+        command.response_type_set(MCIHostResponseType::R1);
+        command.response_error_flags_set(MCIHostCardStatusFlag::ALL_ERROR_FLAG);
+
+        let mut data = MCIHostData::new();
+        data.block_size_set(lock_data.len());
+        data.block_count_set(1);
+        data.timeout_set(self.data_timeout(lock_data.len() as u32, 1, true));
+        /* usual data is sent LSB first, keep byte order in memory */
+        let tx_buf = lock_data
+            .chunks(4)
+            .map(|bytes| {
+                let mut word = [0u8; 4];
+                word[..bytes.len()].copy_from_slice(bytes);
+                u32::from_ne_bytes(word)
+            })
+            .collect::<Vec<u32>>();
+        data.tx_data_copy(&tx_buf)?;
+
+        let mut content = MCIHostTransfer::new();
+        content.set_cmd(Some(command));
+        content.set_data(Some(data));
+
+        if let Err(err) = host.dev.transfer_function(&mut content, host) {
+            let command = content.cmd().unwrap();
+            let response = command.response();
+            info!(
+                "\r\nError: send CMD42 failed with host error {:?}, response 0x{:x}\r\n",
+                err, response[0]
+            );
+            return Err(MCIHostError::TransferFailed);
+        }
+
+        let command = content.cmd().unwrap();
+        let response = command.response();
+        if response[0] & MCIHostCardStatusFlag::ALL_ERROR_FLAG.bits() != 0 {
+            info!(
+                "\r\nError: CMD42 response error, response 0x{:x}\r\n",
+                response[0]
+            );
+            return Err(MCIHostError::LockUnlockFailed);
+        }
+
+        Ok(())
+    }
+}
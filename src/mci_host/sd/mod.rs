@@ -2,13 +2,24 @@
 mod cid;
 pub(crate) mod consts;
 mod csd;
+mod drive;
 mod io_voltage;
+mod lock;
+mod nonblock;
+mod power;
+mod raw_cmd;
+mod recovery;
+mod scatter;
 mod scr;
+mod speed_class;
+mod stats;
 mod status;
+mod trace;
 mod usr_param;
+mod write_protect;
 
 use alloc::boxed::Box;
-use alloc::rc::Rc;
+use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::cmp::max;
@@ -17,14 +28,17 @@ use core::str;
 use core::sync::atomic::{AtomicPtr, Ordering};
 use core::time::Duration;
 use io_voltage::SdIoVoltage;
+use nonblock::SdPendingRead;
+use recovery::SdRecoveryStage;
 
+use crate::mci::{MCIIrqHandler, MCITimingProfile};
 use crate::mci_host::mci_host_config::MCIHostType;
 use crate::mci_host::mci_sdif::sdif_device::SDIFDev;
 use crate::mci_host::MCIHost;
+use crate::osa::dma_buf::DmaBuf;
 use crate::osa::osa_init;
-use crate::osa::pool_buffer::PoolBuffer;
-use crate::tools::swap_word_byte_sequence_u32;
-use crate::{sleep, IoPad};
+use crate::tools::{swap_word_byte_sequence_u32, Deadline};
+use crate::{now, sleep, IoPad};
 
 use super::constants::*;
 use super::err::{MCIHostError, MCIHostStatus};
@@ -35,9 +49,15 @@ use super::mci_host_transfer::{MCIHostCmd, MCIHostData, MCIHostTransfer};
 use super::mci_sdif::consts::SDStatus;
 use cid::SdCid;
 use consts::*;
+pub use consts::{SdDriverStrength, SdMaxCurrent};
 use csd::{CsdFlags, SdCardCmdClass, SdCsd};
+pub use io_voltage::SdIoVoltageFn;
 use log::{debug, error, info, warn};
+pub use power::SdPowerState;
+pub use raw_cmd::{SdRawCmd, SdRawData, SdRawResponse};
 use scr::{ScrFlags, SdScr};
+use speed_class::SdRecording;
+pub use stats::SdTransferStats;
 use status::SdStatus;
 use usr_param::SdUsrParam;
 
@@ -59,15 +79,28 @@ pub struct SdCard {
     flags: SdCardFlag,
     block_count: u32,
     current_timing: SdTimingMode,
+    bus_width: MCIHostBusWdith,
     driver_strength: SdDriverStrength,
     max_current: SdMaxCurrent,
     operation_voltage: MCIHostOperationVoltage,
     cid: SdCid,
     csd: SdCsd,
+    raw_csd: [u32; 4],
     scr: SdScr,
     stat: SdStatus,
+    is_locked: bool,
+    bus_timing_pending: bool,
+    stats: SdTransferStats,
+    pending_read: Option<SdPendingRead>,
+    power_state: SdPowerState,
+    in_recovery: bool,
+    recording: Option<SdRecording>,
 }
 
+/* 卡句柄可以交给其他核, 控制器访问在 SDIFDev 内部加锁串行 */
+const _: fn() = assert_send_sync::<SdCard>;
+fn assert_send_sync<T: Send + Sync>() {}
+
 impl SdCard {
     pub fn new(addr: NonNull<u8>, iopad: IoPad) -> Self {
         osa_init();
@@ -75,8 +108,8 @@ impl SdCard {
         let mci_host_config = MCIHostConfig::new();
 
         // 组装 base
-        let internal_buffer = match PoolBuffer::new(
-            mci_host_config.max_trans_size,
+        let internal_buffer = match DmaBuf::<u32>::new(
+            mci_host_config.max_trans_size / size_of::<u32>(),
             mci_host_config.def_block_size,
         ) {
             Err(e) => panic!("Failed to allocate internal buffer, err: {:?}", e),
@@ -86,7 +119,7 @@ impl SdCard {
         info!(
             "Internal buffer@0x{:p}, length = 0x{}",
             base.internal_buffer.addr().as_ptr(),
-            base.internal_buffer.size()
+            base.internal_buffer.capacity() * size_of::<u32>()
         );
 
         // 组装 host
@@ -113,13 +146,26 @@ impl SdCard {
         sd_card
     }
 
+    /// Interrupt handler of this card's controller, register it before [`SdCard::init`]
+    pub fn irq_handler(&self) -> MCIHostStatus<MCIIrqHandler> {
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        Ok(host.dev.irq_handler())
+    }
+
+    /// Use board specific timings for card clocks in `profile`, call it before [`SdCard::init`]
+    pub fn timing_profile_set(&mut self, profile: MCITimingProfile) -> MCIHostStatus {
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        host.dev.timing_profile_set(Some(profile));
+        Ok(())
+    }
+
     fn sdif_config(&mut self) -> MCIHostStatus {
         let mut card_cd = MCIHostCardDetect::new();
 
         card_cd.typ = MCIHostDetectCardType::ByHostCD;
         card_cd.cd_debounce_ms = 10;
 
-        let card_cd = Rc::new(card_cd);
+        let card_cd = Arc::new(card_cd);
 
         let usr_param = &mut self.usr_param;
 
@@ -131,9 +177,7 @@ impl SdCard {
             | MCIHostCapability::BIT8_DATA_WIDTH
             | MCIHostCapability::DETECT_CARD_BY_DATA3
             | MCIHostCapability::DETECT_CARD_BY_CD
-            | MCIHostCapability::AUTO_CMD12
-            | MCIHostCapability::DRIVER_TYPE_C
-            | MCIHostCapability::SET_CURRENT;
+            | MCIHostCapability::AUTO_CMD12;
         let capability = capability.bits() | MCIHostCapabilityExt::BIT8_WIDTH.bits();
 
         usr_param.capability = capability;
@@ -154,7 +198,8 @@ impl SdCard {
                 | MCIHostCapability::VOLTAGE_1V8
                 | MCIHostCapability::HIGH_SPEED
                 | MCIHostCapability::SDR104
-                | MCIHostCapability::SDR50;
+                | MCIHostCapability::SDR50
+                | MCIHostCapability::DDR_MODE;
 
             host.capability = capability;
         } else {
@@ -173,8 +218,10 @@ impl SdCard {
 
         self.usr_param.cd = Some(card_cd.clone());
 
-        host.max_block_count
-            .set(host.config.max_trans_size as u32 / host.config.def_block_size as u32);
+        host.max_block_count.store(
+            host.config.max_trans_size as u32 / host.config.def_block_size as u32,
+            Ordering::Relaxed,
+        );
         host.max_block_size = MCI_HOST_MAX_BLOCK_LENGTH;
         host.source_clock_hz = 1200000000;
         host.cd = Some(card_cd.clone());
@@ -194,13 +241,22 @@ impl SdCard {
             flags: SdCardFlag::empty(),
             block_count: 0,
             current_timing: SdTimingMode::SDR12DefaultMode,
+            bus_width: MCIHostBusWdith::Bit1,
             driver_strength: SdDriverStrength::TypeB,
             max_current: SdMaxCurrent::Limit200mA,
             operation_voltage: MCIHostOperationVoltage::Voltage330V,
             cid: SdCid::new(),
             csd: SdCsd::new(),
+            raw_csd: [0; 4],
             scr: SdScr::new(),
             stat: SdStatus::new(),
+            is_locked: false,
+            bus_timing_pending: false,
+            stats: SdTransferStats::default(),
+            pending_read: None,
+            power_state: SdPowerState::Active,
+            in_recovery: false,
+            recording: None,
         }
     }
 }
@@ -229,6 +285,10 @@ impl SdCard {
                     warn!("SD card init failed !!! {:?}", err);
                     return Err(MCIHostError::CardInitFailed);
                 }
+                if self.is_locked {
+                    warn!("SD card is locked, unlock it before data access");
+                    return Err(MCIHostError::CardLocked);
+                }
             }
         }
 
@@ -254,9 +314,19 @@ impl SdCard {
         info!("card init proc");
         /* reset variables */
         self.flags = SdCardFlag::empty();
+        self.is_locked = false;
+        self.bus_timing_pending = false;
+        /* CMD0 后卡的驱动强度和电流限制恢复为默认值 */
+        self.driver_strength = SdDriverStrength::TypeB;
+        self.max_current = SdMaxCurrent::Limit200mA;
+        /* 重新初始化后卡退出录制状态 */
+        self.recording = None;
         /* set DATA bus width */
         let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        /* 识别阶段按 SDR 传输, 关闭上次选择 DDR50 时打开的 DDR 模式 */
+        host.dev.enable_ddr_mode(false, 0);
         host.dev.card_bus_width_set(MCIHostBusWdith::Bit1);
+        self.bus_width = MCIHostBusWdith::Bit1;
         /*set card freq to 400KHZ*/
         self.base.bus_clk_hz = host.dev.card_clock_set(MCI_HOST_CLOCK_400KHZ, host);
 
@@ -293,6 +363,10 @@ impl SdCard {
             return Err(MCIHostError::SelectCardFailed);
         }
 
+        /* a locked card only responds to basic, lock card and application commands */
+        self.is_locked =
+            self.card_status_get()? & MCIHostCardStatusFlag::CARD_IS_LOCKED.bits() != 0;
+
         /* Set to max frequency in non-high speed mode. */
         /*
          * With card in data transfer state, we can set SD clock to maximum
@@ -322,6 +396,7 @@ impl SdCard {
             }
             let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
             host.dev.card_bus_width_set(MCIHostBusWdith::Bit4);
+            self.bus_width = MCIHostBusWdith::Bit4;
         }
 
         /* try to get card current status */
@@ -336,6 +411,13 @@ impl SdCard {
             return Err(MCIHostError::SetCardBlockSizeFailed);
         }
 
+        if self.is_locked {
+            /* switch function is not allowed until card unlocked */
+            info!("card is locked, bus timing select deferred");
+            self.bus_timing_pending = true;
+            return Ok(());
+        }
+
         /* SDR104, SDR50, and DDR50 mode need tuning */
         if self.bus_timing_select().is_err() {
             return Err(MCIHostError::SwitchBusTimingFailed);
@@ -429,20 +511,28 @@ impl SdCard {
                 }
 
                 match self.voltage_switch(MCIHostOperationVoltage::Voltage180V) {
+                    /* card enters UHS-I mode and input/ouput timings are changed to SDR12 by default */
+                    Ok(()) => {
+                        info!("Select 1.8v");
+                        self.operation_voltage = MCIHostOperationVoltage::Voltage180V;
+                        break;
+                    }
+                    /* card rejected CMD11 and keeps working at 3.3V */
                     Err(MCIHostError::SwitchVoltageFail) => {
+                        self.flags &= !SdCardFlag::SupportVoltage180v;
                         break;
                     }
-                    /* card enters UHS-I mode and input/ouput timings are changed to SDR12 by default */
+                    /* card has been power cycled, identify it again at 3.3V without S18R */
                     Err(MCIHostError::SwitchVoltage18VFail33VSuccess) => {
                         acmd41_argument &= !MCIHostOCR::SWITCH_18_REQUEST_FLAG;
                         self.flags &= !SdCardFlag::SupportVoltage180v;
+                        /* 重新上电后卡需要至少 74 个时钟才能接收 CMD0 */
+                        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+                        host.dev.card_active_send();
                         continue;
                     }
-                    _ => {
-                        info!("Select 1.8v");
-                        self.operation_voltage = MCIHostOperationVoltage::Voltage180V;
-                        break;
-                    }
+                    /* 包括无法切回 3.3V, 信号电压不确定, 终止初始化 */
+                    Err(err) => return Err(err),
                 }
             }
             break;
@@ -464,13 +554,14 @@ impl SdCard {
         }
 
         if typ == SdIoVoltageCtrlType::ByGpio {
-            /* make sure card signal line voltage is 3.3v before initalization */
-            if let Some(func) = io_voltage.func() {
-                func(voltage);
+            /* 由用户回调切换板上的调压器 */
+            let func = io_voltage.func().ok_or(MCIHostError::NotSupportYet)?;
+            if !func(voltage) {
+                return Err(MCIHostError::SwitchVoltageFail);
             }
         } else if typ == SdIoVoltageCtrlType::ByHost {
             let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
-            let _ = host.dev.switch_to_voltage(voltage, host);
+            host.dev.switch_to_voltage(voltage, host)?;
         } else {
             return Err(MCIHostError::NotSupportYet);
         }
@@ -538,6 +629,20 @@ impl SdCard {
         Ok(())
     }
 
+    /// 当前卡检测状态, 不做去抖
+    fn card_is_inserted(&self) -> bool {
+        match self.usr_param.cd.as_ref() {
+            Some(cd) if cd.typ == MCIHostDetectCardType::ByGpioCD => cd
+                .card_detected
+                .is_some_and(|card_detected| card_detected()),
+            _ => self
+                .base
+                .host
+                .as_ref()
+                .is_some_and(|host| host.dev.card_is_inserted()),
+        }
+    }
+
     fn polling_card_insert(&self, status: SDStatus) -> MCIHostStatus {
         let cd = self
             .usr_param
@@ -548,19 +653,8 @@ impl SdCard {
         if cd.typ == MCIHostDetectCardType::ByGpioCD {
             let card_detect = cd.card_detected.ok_or(MCIHostError::Fail)?;
 
-            loop {
-                if card_detect() && status == SDStatus::Inserted {
-                    let cd_debounce_ms = cd.cd_debounce_ms;
-                    sleep(Duration::from_millis(cd_debounce_ms as u64));
-                    if card_detect() {
-                        break;
-                    }
-                }
-
-                if !card_detect() && status == SDStatus::Removed {
-                    break;
-                }
-            }
+            /* wait until card inserted or removed, no timeout */
+            cd.wait_stable(card_detect, status == SDStatus::Inserted, None)?;
         } else {
             /* mostly advanced host not detect card by gpio, therefore follow this branch */
             if self.base.is_host_ready == false {
@@ -572,7 +666,7 @@ impl SdCard {
             let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
             if host
                 .dev
-                .card_detect_status_polling(status, u32::MAX, host)
+                .card_detect_status_polling(status, SD_CARD_DETECT_TIMEOUT, host)
                 .is_err()
             {
                 info!("Polling SD card status failed !!!");
@@ -582,22 +676,41 @@ impl SdCard {
         Ok(())
     }
 
-    fn polling_card_status_busy(&mut self, timeout_ms: u32) -> MCIHostStatus {
-        let mut status_timeout_us = timeout_ms * 1000;
+    fn polling_card_status_busy(&mut self, timeout: Duration) -> MCIHostStatus {
+        let deadline = Deadline::after(timeout);
 
-        while status_timeout_us > 0 {
+        loop {
+            let expired = deadline.is_expired();
             let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
-            if !host.dev.card_is_busy() {
-                if Err(MCIHostError::CardStatusIdle) == self.card_status_send() {
-                    return Err(MCIHostError::CardStatusIdle);
-                }
-            } else {
-                /* Delay 125us to throttle the polling rate */
-                sleep(Duration::from_micros(125));
-                status_timeout_us -= 125;
+            if !host.dev.card_is_busy()
+                && Err(MCIHostError::CardStatusIdle) == self.card_status_send()
+            {
+                return Err(MCIHostError::CardStatusIdle);
+            }
+
+            if expired {
+                return Err(MCIHostError::CardStatusBusy);
             }
+
+            /* Delay 125us to throttle the polling rate */
+            sleep(Duration::from_micros(125));
         }
-        Err(MCIHostError::CardStatusBusy)
+    }
+
+    /// 数据阶段超时, 每块为 CSD 推算的访问时间加上 1 线模式下的传输时间
+    fn data_timeout(&self, block_size: u32, block_count: u32, is_write: bool) -> Duration {
+        let access_time = if !is_write {
+            self.csd.read_timeout(self.base.bus_clk_hz)
+        } else if self.flags.contains(SdCardFlag::SupportSdxc) {
+            SDXC_WRITE_TIMEOUT
+        } else {
+            self.csd.write_timeout(self.base.bus_clk_hz)
+        };
+        let transfer_time = Duration::from_nanos(
+            (block_size as u64 * 8 * 1_000_000_000) / max(self.base.bus_clk_hz, 1) as u64,
+        );
+
+        (access_time + transfer_time) * block_count
     }
 
     fn write_successful_block_send(&mut self, blocks: &mut u32) -> MCIHostStatus {
@@ -622,8 +735,7 @@ impl SdCard {
         let mut data = MCIHostData::new();
         data.block_size_set(4);
         data.block_count_set(1);
-        let rx_buf = vec![0; 4];
-        data.rx_data_set(Some(rx_buf));
+        data.rx_data_alloc(4)?;
 
         let mut content = MCIHostTransfer::new();
         content.set_cmd(Some(command));
@@ -649,7 +761,10 @@ impl SdCard {
         if self.operation_voltage != MCIHostOperationVoltage::Voltage180V {
             /* group 1, function 1 ->high speed mode*/
             debug!("group1");
-            match self.func_select(SdGroupNum::TimingMode, SdTimingFuncNum::SDR25HighSpeed) {
+            match self.func_select(
+                SdGroupNum::TimingMode,
+                SdTimingFuncNum::SDR25HighSpeed as u32,
+            ) {
                 Ok(_) => {
                     /* If the result isn't "switching to high speed mode(50MHZ) successfully or card doesn't support high speed
                      * mode". Return failed status. */
@@ -683,11 +798,14 @@ impl SdCard {
                         host.capability
                     };
                     if host_capability.contains(MCIHostCapability::SDR104) {
-                        match self.func_select(SdGroupNum::TimingMode, SdTimingFuncNum::SDR104) {
+                        self.uhs_drive_select(SdTimingMode::SDR104Mode)?;
+                        match self
+                            .func_select(SdGroupNum::TimingMode, SdTimingFuncNum::SDR104 as u32)
+                        {
                             Ok(_) => {
+                                self.current_timing = SdTimingMode::SDR104Mode;
                                 let host =
                                     self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
-                                self.current_timing = SdTimingMode::SDR104Mode;
                                 self.base.bus_clk_hz =
                                     host.dev.card_clock_set(SD_CLOCK_208MHZ, host);
                                 break;
@@ -703,26 +821,60 @@ impl SdCard {
                 if self.current_timing == SdTimingMode::SDR50Mode {
                     let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
                     if host.capability.contains(MCIHostCapability::SDR50) {
-                        match self.func_select(SdGroupNum::TimingMode, SdTimingFuncNum::SDR50) {
+                        self.uhs_drive_select(SdTimingMode::SDR50Mode)?;
+                        match self
+                            .func_select(SdGroupNum::TimingMode, SdTimingFuncNum::SDR50 as u32)
+                        {
                             Ok(_) => {
+                                self.current_timing = SdTimingMode::SDR50Mode;
                                 let host =
                                     self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
-                                self.current_timing = SdTimingMode::SDR50Mode;
                                 self.base.bus_clk_hz =
                                     host.dev.card_clock_set(SD_CLOCK_100MHZ, host);
                                 break;
                             }
                             _ => {
                                 info!("\r\nNote: SDR50 mode is not supported\r\n");
-                                self.current_timing = SdTimingMode::SDR25HighSpeedMode;
+                                self.current_timing = SdTimingMode::DDR50Mode;
                             }
                         }
                     }
                 }
 
+                if self.current_timing == SdTimingMode::DDR50Mode {
+                    let (host_capability, ddr_timing) = {
+                        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+                        (
+                            host.capability,
+                            host.dev.ddr_timing_available(SD_CLOCK_50MHZ),
+                        )
+                    };
+                    /* 没有内置 DDR 时序, 板级时序表中没有 DDR50 时不切换 */
+                    if host_capability.contains(MCIHostCapability::DDR_MODE) && ddr_timing {
+                        self.uhs_drive_select(SdTimingMode::DDR50Mode)?;
+                        if self
+                            .func_select(SdGroupNum::TimingMode, SdTimingFuncNum::DDR50 as u32)
+                            .is_ok()
+                        {
+                            self.current_timing = SdTimingMode::DDR50Mode;
+                            let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+                            /* 先打开 DDR 模式, 时钟按 DDR 时序配置 */
+                            host.dev.enable_ddr_mode(true, 0);
+                            self.base.bus_clk_hz = host.dev.card_clock_set(SD_CLOCK_50MHZ, host);
+                            break;
+                        }
+                    }
+                    info!("\r\nNote: DDR50 mode is not supported\r\n");
+                    self.current_timing = SdTimingMode::SDR25HighSpeedMode;
+                }
+
                 if self.current_timing == SdTimingMode::SDR25HighSpeedMode {
-                    match self.func_select(SdGroupNum::TimingMode, SdTimingFuncNum::SDR25HighSpeed)
-                    {
+                    /* 前面尝试 UHS 模式时可能提高了电流限制 */
+                    self.uhs_drive_select(SdTimingMode::SDR25HighSpeedMode)?;
+                    match self.func_select(
+                        SdGroupNum::TimingMode,
+                        SdTimingFuncNum::SDR25HighSpeed as u32,
+                    ) {
                         Ok(_) => {
                             let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
                             self.current_timing = SdTimingMode::SDR25HighSpeedMode;
@@ -764,7 +916,51 @@ impl SdCard {
         Ok(())
     }
 
-    fn func_select(&mut self, group: SdGroupNum, func: SdTimingFuncNum) -> MCIHostStatus {
+    fn func_select(&mut self, group: SdGroupNum, func: u32) -> MCIHostStatus {
+        /* Check if card support high speed mode. */
+        let (support, current) = self.func_check(group, func)?;
+
+        /* check if function is support */
+        if (support & (1 << func) == 0) || current != func {
+            info!(
+                "\r\nError: function {} in group {} not support\r\n",
+                func, group as u32
+            );
+            return Err(MCIHostError::CardNotSupport);
+        }
+
+        let func_status = match self.func_swtich(SdSwitchMode::Set, group, func) {
+            Some(status) => status,
+            None => return Err(MCIHostError::TransferFailed),
+        };
+
+        /* convert to little endian sequence */
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        let mut func_status_need_convert = func_status[3..].to_vec();
+        let _ = host.dev.convert_data_to_little_endian(
+            &mut func_status_need_convert,
+            2,
+            MCIHostDataPacketFormat::MSBFirst,
+            host,
+        );
+        let mut func_status = func_status[0..3].to_vec();
+        func_status.extend_from_slice(&func_status_need_convert);
+
+        /* According to the "switch function status[bits 511~0]" return by switch command in mode "set function":
+            -check if group 1 is successfully changed to function 1 by checking if bits 379~376 equal value 1;
+        */
+        let current_func_status = ((func_status[3] & 0xff) << 8) | (func_status[4] >> 24);
+
+        if ((current_func_status >> (group as u32) * 4) & 0xf) != func {
+            info!("\r\nError: switch to function {} failed\r\n", func);
+            return Err(MCIHostError::SwitchFailed);
+        }
+
+        Ok(())
+    }
+
+    /// CMD6 查询模式, 返回 `group` 支持的功能位图, 以及 `func` 可切换时卡返回的功能号
+    fn func_check(&mut self, group: SdGroupNum, func: u32) -> MCIHostStatus<(u16, u32)> {
         /* check if card support CMD6 */
         if (self.version as u32 <= SdSpecificationVersion::Version1_0 as u32)
             || (self.csd.card_command_classes & SdCardCmdClass::Switch.bits() == 0)
@@ -773,7 +969,6 @@ impl SdCard {
             return Err(MCIHostError::CardNotSupport);
         }
 
-        /* Check if card support high speed mode. */
         let mut func_status = match self.func_swtich(SdSwitchMode::Check, group, func) {
             Some(status) => status,
             None => return Err(MCIHostError::TransferFailed),
@@ -809,45 +1004,10 @@ impl SdCard {
 
         let current_func_status = ((func_status[3] & 0xff) << 8) | (func_status[4] >> 24);
 
-        /* check if function is support */
-        if (func_group_info[group as usize] & (1 << (func as u16)) == 0)
-            || (((current_func_status >> (group as u32) * 4) & 0xf) != (func as u32))
-        {
-            info!(
-                "\r\nError: function {} in group {} not support\r\n",
-                func as u32, group as u32
-            );
-            return Err(MCIHostError::CardNotSupport);
-        }
-
-        let func_status = match self.func_swtich(SdSwitchMode::Set, group, func) {
-            Some(status) => status,
-            None => return Err(MCIHostError::TransferFailed),
-        };
-
-        /* convert to little endian sequence */
-        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
-        let mut func_status_need_convert = func_status[3..].to_vec();
-        let _ = host.dev.convert_data_to_little_endian(
-            &mut func_status_need_convert,
-            2,
-            MCIHostDataPacketFormat::MSBFirst,
-            host,
-        );
-        let mut func_status = func_status[0..3].to_vec();
-        func_status.extend_from_slice(&func_status_need_convert);
-
-        /* According to the "switch function status[bits 511~0]" return by switch command in mode "set function":
-            -check if group 1 is successfully changed to function 1 by checking if bits 379~376 equal value 1;
-        */
-        let current_func_status = ((func_status[3] & 0xff) << 8) | (func_status[4] >> 24);
-
-        if ((current_func_status >> (group as u32) * 4) & 0xf) != (func as u32) {
-            info!("\r\nError: switch to function {} failed\r\n", func as u32);
-            return Err(MCIHostError::SwitchFailed);
-        }
-
-        Ok(())
+        Ok((
+            func_group_info[group as usize],
+            (current_func_status >> (group as u32) * 4) & 0xf,
+        ))
     }
 
     fn execute_tuning(&mut self) -> MCIHostStatus {
@@ -864,15 +1024,22 @@ impl SdCard {
         start_block: u32,
         block_count: u32,
     ) -> MCIHostStatus {
+        if self.is_locked {
+            return Err(MCIHostError::CardLocked);
+        }
+        if self.power_state != SdPowerState::Active {
+            return Err(MCIHostError::CardSuspended);
+        }
+
         buffer.clear();
         let mut block_left = block_count;
         let mut block_count_one_time: u32;
 
         while block_left != 0 {
             let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
-            if block_left > host.max_block_count.get() {
-                block_left -= host.max_block_count.get();
-                block_count_one_time = host.max_block_count.get();
+            if block_left > host.max_block_count.load(Ordering::Relaxed) {
+                block_left -= host.max_block_count.load(Ordering::Relaxed);
+                block_count_one_time = host.max_block_count.load(Ordering::Relaxed);
             } else {
                 block_count_one_time = block_left;
                 block_left = 0;
@@ -885,17 +1052,12 @@ impl SdCard {
              */
             let len = block_count_one_time * MCI_HOST_DEFAULT_BLOCK_SIZE / 4;
             let mut once_buffer = vec![0u32; len as usize];
-            if self
-                .read(
-                    &mut once_buffer,
-                    start_block,
-                    MCI_HOST_DEFAULT_BLOCK_SIZE,
-                    block_count_one_time,
-                )
-                .is_err()
-            {
-                return Err(MCIHostError::TransferFailed);
-            }
+            self.read(
+                &mut once_buffer,
+                start_block,
+                MCI_HOST_DEFAULT_BLOCK_SIZE,
+                block_count_one_time,
+            )?;
 
             buffer.extend(once_buffer.iter());
         }
@@ -905,43 +1067,47 @@ impl SdCard {
 
     pub fn write_blocks(
         &mut self,
-        buffer: &mut Vec<u32>,
+        buffer: &[u32],
         start_block: u32,
         block_count: u32,
     ) -> MCIHostStatus {
+        if self.is_locked {
+            return Err(MCIHostError::CardLocked);
+        }
+        if self.power_state != SdPowerState::Active {
+            return Err(MCIHostError::CardSuspended);
+        }
+        if self.is_read_only() {
+            return Err(MCIHostError::ReadOnly);
+        }
+
         let mut block_left = block_count;
         let mut block_count_one_time: u32;
         let mut block_written_one_time = 0; // 一次写操作写成功的块数
 
         while block_left != 0 {
             let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
-            if block_left > host.max_block_count.get() {
-                block_count_one_time = host.max_block_count.get();
+            if block_left > host.max_block_count.load(Ordering::Relaxed) {
+                block_count_one_time = host.max_block_count.load(Ordering::Relaxed);
             } else {
                 block_count_one_time = block_left;
             }
 
-            let len = MCI_HOST_DEFAULT_BLOCK_SIZE * block_count_one_time / 4;
-            let mut once_buffer = vec![0u32; len as usize];
             let start_addr = (block_count - block_left) * MCI_HOST_DEFAULT_BLOCK_SIZE / 4;
             let end_addr = start_addr + block_count_one_time * MCI_HOST_DEFAULT_BLOCK_SIZE / 4;
             debug!(
                 "write block(s) one time, relative addr(u32) from {} - {}, block count {}",
                 start_addr, end_addr, block_count_one_time
             );
-            once_buffer.copy_from_slice(&buffer[start_addr as usize..end_addr as usize]);
-            if self
-                .write(
-                    &mut once_buffer,
-                    start_block + block_count - block_left,
-                    MCI_HOST_DEFAULT_BLOCK_SIZE,
-                    block_count_one_time,
-                    &mut block_written_one_time,
-                )
-                .is_err()
-            {
-                error!("write block(s) failed!");
-                return Err(MCIHostError::TransferFailed);
+            if let Err(err) = self.write(
+                &buffer[start_addr as usize..end_addr as usize],
+                start_block + block_count - block_left,
+                MCI_HOST_DEFAULT_BLOCK_SIZE,
+                block_count_one_time,
+                &mut block_written_one_time,
+            ) {
+                error!("write block(s) failed! {:?}", err);
+                return Err(err);
             }
 
             block_left -= block_count_one_time;
@@ -951,6 +1117,10 @@ impl SdCard {
     }
 
     fn erase(&mut self, start_block: u32, block_count: u32) -> MCIHostStatus {
+        if self.is_read_only() {
+            return Err(MCIHostError::ReadOnly);
+        }
+
         let mut erase_block_start = start_block;
         let mut erase_block_end = erase_block_start + block_count - 1;
 
@@ -1007,58 +1177,50 @@ impl SdCard {
         Ok(())
     }
 
+    /// 失败时按主机的 [`MCIHostRecoveryPolicy`] 逐级恢复, 仍失败则返回最后一次的错误,
+    /// `retry` 为 0 的命令属于多条命令组成的序列, 只复位控制器, 不重试
     fn transfer(&mut self, content: &mut MCIHostTransfer, retry: u32) -> MCIHostStatus {
-        let mut retry = retry;
-        let mut retuning_count = 3;
+        if self.power_state != SdPowerState::Active {
+            return Err(MCIHostError::CardSuspended);
+        }
+
+        let policy = self.recovery_policy();
+        let mut retry = retry.min(policy.retries);
+        /* 恢复过程中发出的命令不再升级, 避免重新初始化时递归 */
+        let escalate = retry != 0 && !self.in_recovery;
+        let mut stage = SdRecoveryStage::Retune;
         loop {
             let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
-            let status = host.dev.transfer_function(content, host);
-            if status.is_ok() {
-                break;
-            }
-
-            /* if transfer data failed, send cmd12 to abort current transfer */
-            if content.data().is_some() {
-                let _ = self.transmission_stop();
-                /* when transfer error occur, polling card status until it is ready for next data transfer, otherwise the
-                 * retry transfer will fail again */
-                if Err(MCIHostError::CardStatusIdle)
-                    != self.polling_card_status_busy(SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT)
-                {
-                    return Err(MCIHostError::TransferFailed);
-                }
+            let err = match host.dev.transfer_function(content, host) {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            let index = content.cmd().map_or(0, |cmd| cmd.index());
+            warn!("CMD{} failed with host error {:?}", index, err);
+
+            if let Err(recover_err) = self.transfer_abort(content.data().is_some()) {
+                info!(
+                    "\r\nError: recover from CMD{} failure failed {:?}\r\n",
+                    index, recover_err
+                );
+                return Err(err);
             }
 
-            if retry == 0 || status == Err(MCIHostError::ReTuningRequest) {
-                if self.current_timing == SdTimingMode::SDR104Mode
-                    || self.current_timing == SdTimingMode::SDR50Mode
-                {
-                    if retuning_count == 0 {
-                        break;
-                    }
-                    retuning_count -= 1;
-                    /* Perform retuning, CMD19 sends a tuning block to the host to determine sampling point.
-                    UHS50 and UHS104 cards support CMD19 in 1.8V signaling. Sampling
-                    clock tuning is required for UHS104 host and optional for UHS50 host. */
-                    if self.execute_tuning().is_err() {
-                        info!("\r\nError: retuning failed.\r\n");
-                        return Err(MCIHostError::TuningFail);
-                    } else {
-                        info!("\r\nlog: retuning successfully.\r\n");
-                        continue;
-                    }
-                }
-            } else {
-                break;
+            /* 采样点偏移时原样重试没有意义, 直接调谐 */
+            if retry > 0 && err != MCIHostError::ReTuningRequest {
+                retry -= 1;
+                self.stats.retries += 1;
+                continue;
             }
 
-            if retry != 0 {
-                retry -= 1;
-            } else {
-                break;
+            if !escalate || !self.recovery_escalate(&mut stage, &policy) {
+                info!(
+                    "\r\nError: CMD{} failed after recovery, error {:?}\r\n",
+                    index, err
+                );
+                return Err(err);
             }
         }
-        Ok(())
     }
 }
 
@@ -1089,7 +1251,6 @@ impl SdCard {
         let response = command.response();
 
         self.base.internal_buffer.clear();
-        // self.base.internal_buffer.extend(response.iter().flat_map(|&val| val.to_ne_bytes()));
         if self.base.internal_buffer.copy_from_slice(response).is_err() {
             return Err(MCIHostError::Fail);
         }
@@ -1104,8 +1265,8 @@ impl SdCard {
         &mut self,
         mode: SdSwitchMode,
         group: SdGroupNum,
-        num: SdTimingFuncNum,
-    ) -> Option<Vec<u32>> {
+        num: u32,
+    ) -> Option<DmaBuf<u32>> {
         let host = self.base.host.as_ref()?;
 
         let mut command = MCIHostCmd::new();
@@ -1114,7 +1275,7 @@ impl SdCard {
         command.argument_set({
             let mut arg = (mode as u32) << 31 | 0x00FFFFFF;
             arg &= !(0xf << ((group as u32) * 4));
-            arg |= (num as u32) << ((group as u32) * 4);
+            arg |= num << ((group as u32) * 4);
             arg
         });
         command.response_type_set(MCIHostResponseType::R1);
@@ -1123,19 +1284,11 @@ impl SdCard {
 
         data.block_size_set(64);
         data.block_count_set(1);
-        // We implemented 'Drop' for PoolBuffer, so we don't need to dealloc this rx_buf
-        let rx_buf = match PoolBuffer::new(64 * size_of::<u32>(), size_of::<u32>()) {
-            Err(e) => panic!("CMD6 allocate rx_buf failed, err {:?}", e),
-            Ok(buf) => {
-                warn!(
-                    "CMD6: alloc rx_buf at {:p}, size {}",
-                    buf.addr().as_ptr(),
-                    buf.size()
-                );
-                buf
-            }
-        };
-        data.rx_data_set(Some(rx_buf.to_vec::<u32>().unwrap()));
+        /* 数据直接收进 DMA 缓冲区, 总线地址随缓冲区交给控制器 */
+        if let Err(err) = data.rx_data_alloc(16) {
+            info!("\r\nError: CMD6 allocate rx_buf failed {:?}\r\n", err);
+            return None;
+        }
 
         let mut content = MCIHostTransfer::new();
 
@@ -1252,9 +1405,8 @@ impl SdCard {
         let response = command.response();
 
         self.base.internal_buffer.clear();
-        // self.base.internal_buffer.extend(response.iter().flat_map(|&val| val.to_ne_bytes()));
         if let Err(e) = self.base.internal_buffer.copy_from_slice(response) {
-            error!("copy to PoolBuffer failed! err: {:?}", e);
+            error!("copy to internal buffer failed! err: {:?}", e);
             return Err(MCIHostError::Fail);
         }
 
@@ -1276,14 +1428,23 @@ impl SdCard {
         let mut content = MCIHostTransfer::new();
         content.set_cmd(Some(command));
 
-        if host.dev.transfer_function(&mut content, host).is_err() {
-            return Err(MCIHostError::TransferFailed);
+        /* 没有响应时不能确定卡是否已开始切换, 只能重新上电 */
+        if let Err(err) = host.dev.transfer_function(&mut content, host) {
+            info!("\r\nError: send CMD11 failed with host error {:?}\r\n", err);
+            return self.voltage_switch_fallback();
+        }
+        let response = content.cmd().unwrap().response()[0];
+        if response & MCIHostCardStatusFlag::ALL_ERROR_FLAG.bits() != 0 {
+            /* 卡拒绝了 CMD11, 仍工作在 3.3V */
+            info!("\r\nError: CMD11 rejected, response 0x{:x}\r\n", response);
+            return Err(MCIHostError::SwitchVoltageFail);
         }
 
         /*
          * Card should drive CMD and DAT[3:0] signals low at the next clock
          * cycle. Some cards will only drive these
-         * lines low briefly, so we should check as soon as possible
+         * lines low briefly, so we should check as soon as possible.
+         * The controller only reports the level of DAT0 (busy), use it for all lines
          */
         if !host.dev.card_is_busy() {
             /* Delay 1ms to allow card to drive lines low */
@@ -1291,7 +1452,7 @@ impl SdCard {
             if !host.dev.card_is_busy() {
                 /* Card did not drive CMD and DAT lines low */
                 info!("\r\nError: card not drive lines low\r\n");
-                return Err(MCIHostError::CardStatusBusy);
+                return self.voltage_switch_fallback();
             }
         }
 
@@ -1302,13 +1463,12 @@ impl SdCard {
         host.dev.card_clock_set(0, host);
 
         /* switch io voltage */
-        if self.switch_io_voltage(voltage) == Err(MCIHostError::NotSupportYet) {
-            info!("Failed to switch SD host to 1.8V");
-            return Err(MCIHostError::SwitchVoltageFail);
+        if let Err(err) = self.switch_io_voltage(voltage) {
+            info!("Failed to switch SD host to 1.8V, error {:?}", err);
+            return self.voltage_switch_fallback();
         }
 
-        /* Gate for 10ms, even though spec requires 5 */
-        sleep(Duration::from_millis(10));
+        sleep(SD_VOLTAGE_SWITCH_CLOCK_GATE);
 
         /* 重新获取 host 实例 */
         let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
@@ -1324,7 +1484,7 @@ impl SdCard {
 
         if host.dev.card_is_busy() {
             info!("Card failed to switch voltages");
-            return Err(MCIHostError::SwitchVoltageFail);
+            return self.voltage_switch_fallback();
         }
 
         info!("Card switched to 1.8V signaling");
@@ -1401,6 +1561,34 @@ impl SdCard {
         Ok(())
     }
 
+    /// CMD 13, return raw card status
+    fn card_status_get(&mut self) -> MCIHostStatus<u32> {
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+
+        let mut command = MCIHostCmd::new();
+
+        command.index_set(MCIHostCommonCmd::SendStatus as u32);
+        command.argument_set(self.base.relative_address << 16);
+        command.response_type_set(MCIHostResponseType::R1);
+
+        let mut content = MCIHostTransfer::new();
+        content.set_cmd(Some(command));
+
+        if let Err(err) = host.dev.transfer_function(&mut content, host) {
+            let command = content.cmd().unwrap();
+            let response = command.response();
+
+            info!(
+                "\r\nError: send CMD13 failed with host error {:?}, response 0x{:x}\r\n",
+                err, response[0]
+            );
+            return Err(err);
+        }
+
+        let command = content.cmd().unwrap();
+        Ok(command.response()[0])
+    }
+
     /// CMD 16
     fn block_size_set(&mut self, block_size: u32) -> MCIHostStatus {
         let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
@@ -1430,6 +1618,8 @@ impl SdCard {
             return Err(MCIHostError::CardNotSupport);
         }
 
+        let start = now();
+
         /* read command are not allowed while card is programming */
         if Err(MCIHostError::CardStatusIdle)
             != self.polling_card_status_busy(SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT)
@@ -1438,6 +1628,30 @@ impl SdCard {
             return Err(MCIHostError::PollingCardIdleFailed);
         }
 
+        let mut context = self.read_content_build(start_block, block_size, block_count)?;
+
+        if let Err(err) = self.transfer(&mut context, 3) {
+            return Err(err);
+        }
+
+        let data = context.data_mut().unwrap();
+        let rx_data = data.rx_data().unwrap();
+        buffer.clear();
+        buffer.extend_from_slice(rx_data);
+
+        self.stats
+            .read_record(block_size * block_count, now() - start);
+
+        Ok(())
+    }
+
+    /// CMD 17/18 传输内容, 接收缓冲区按块大小对齐
+    fn read_content_build(
+        &self,
+        start_block: u32,
+        block_size: u32,
+        block_count: u32,
+    ) -> MCIHostStatus<MCIHostTransfer> {
         let mut command = MCIHostCmd::new();
 
         info!(
@@ -1466,36 +1680,16 @@ impl SdCard {
         let mut data = MCIHostData::new();
         data.block_size_set(block_size as usize);
         data.block_count_set(block_count);
+        data.timeout_set(self.data_timeout(block_size, block_count, false));
 
         let len = block_size * block_count;
-        let rx_buf = match PoolBuffer::new(len as usize, block_size as usize) {
-            Err(e) => panic!("CMD17/18 allocate rx_buf failed, err {:?}", e),
-            Ok(buf) => {
-                warn!(
-                    "CMD17/18: alloc rx_buf at {:p}, size {}",
-                    buf.addr().as_ptr(),
-                    buf.size()
-                );
-                buf
-            }
-        };
-        data.rx_data_set(Some(rx_buf.to_vec::<u32>().unwrap()));
-        data.enable_auto_command12_set(true);
+        data.rx_data_alloc(len as usize / 4)?;
+        self.multi_block_stop_set(&mut data);
 
         let mut context = MCIHostTransfer::new();
         context.set_cmd(Some(command));
         context.set_data(Some(data));
-
-        if let Err(err) = self.transfer(&mut context, 3) {
-            return Err(err);
-        }
-
-        let data = context.data_mut().unwrap();
-        let rx_data = data.rx_data().unwrap();
-        buffer.clear();
-        buffer.extend(rx_data);
-
-        Ok(())
+        Ok(context)
     }
 
     /// CMD 19
@@ -1507,12 +1701,8 @@ impl SdCard {
             .execute_tuning(SdCmd::SendTuningBlock as u32, &mut buffer, 64);
 
         self.base.internal_buffer.clear();
-        let buffer = buffer
-            .iter()
-            .flat_map(|&val| val.to_ne_bytes())
-            .collect::<Vec<u8>>();
-        if let Err(e) = self.base.internal_buffer.copy_from_slice(&buffer[..]) {
-            error!("copy to PoolBuffer failed! err: {:?}", e);
+        if let Err(e) = self.base.internal_buffer.copy_from_slice(&buffer) {
+            error!("copy to internal buffer failed! err: {:?}", e);
             return Err(MCIHostError::Fail);
         }
 
@@ -1522,12 +1712,22 @@ impl SdCard {
     /// CMD 24/25
     pub fn write(
         &mut self,
-        buffer: &mut Vec<u32>,
+        buffer: &[u32],
         start_block: u32,
         block_size: u32,
         block_count: u32,
         written_blocks: &mut u32,
     ) -> MCIHostStatus {
+        if self.is_locked {
+            return Err(MCIHostError::CardLocked);
+        }
+        if self.power_state != SdPowerState::Active {
+            return Err(MCIHostError::CardSuspended);
+        }
+        if self.is_read_only() {
+            return Err(MCIHostError::ReadOnly);
+        }
+
         if (self.flags.contains(SdCardFlag::SupportHighCapacity) && block_size != 512)
             || (block_size > self.base.block_size)
             || ({
@@ -1543,6 +1743,8 @@ impl SdCard {
             return Err(MCIHostError::CardNotSupport);
         }
 
+        let start = now();
+
         if Err(MCIHostError::CardStatusIdle)
             != self.polling_card_status_busy(SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT)
         {
@@ -1566,10 +1768,11 @@ impl SdCard {
         });
 
         let mut data = MCIHostData::new();
-        data.enable_auto_command12_set(false);
         data.block_size_set(block_size as usize);
         data.block_count_set(block_count);
-        data.tx_data_set(Some(buffer.to_vec()));
+        data.timeout_set(self.data_timeout(block_size, block_count, true));
+        data.tx_data_copy(buffer)?;
+        self.multi_block_stop_set(&mut data);
 
         *written_blocks = block_count;
 
@@ -1588,9 +1791,21 @@ impl SdCard {
             debug!("written blocks this time is {}", written_blocks);
         }
 
+        self.stats
+            .write_record(block_size * *written_blocks, now() - start);
+
         Ok(())
     }
 
+    /// 多块传输的结束方式, 卡支持时用 CMD23 预设块数, 否则由控制器自动发送 CMD12
+    fn multi_block_stop_set(&self, data: &mut MCIHostData) {
+        let is_multi_block = data.block_count() > 1;
+        let use_cmd23 = is_multi_block && self.flags.contains(SdCardFlag::SupportSetBlockCountCmd);
+
+        data.enable_auto_command23_set(use_cmd23);
+        data.enable_auto_command12_set(is_multi_block && !use_cmd23);
+    }
+
     /// CMD 55
     fn application_cmd_send(&mut self, relative_address: u32) -> MCIHostStatus {
         let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
@@ -1705,8 +1920,7 @@ impl SdCard {
         let mut data = MCIHostData::new();
         data.block_size_set(64);
         data.block_count_set(1);
-        let rx_buf = PoolBuffer::new(64, 64).unwrap();
-        data.rx_data_set(Some(rx_buf.to_vec::<u32>().unwrap())); // todo 减少内存分配
+        data.rx_data_alloc(16)?;
 
         let mut content = MCIHostTransfer::new();
         content.set_data(Some(data));
@@ -1759,9 +1973,15 @@ impl SdCard {
         let mut content = MCIHostTransfer::new();
         content.set_cmd(Some(command));
 
-        let mut i = MCI_HOST_MAX_CMD_RETRIES;
-        while i > 0 {
+        /* card should finish power up within 1s */
+        let deadline = Deadline::after(SD_INIT_TIMEOUT);
+        loop {
+            let expired = deadline.is_expired();
             if self.application_cmd_send(0).is_err() {
+                if expired {
+                    break;
+                }
+                sleep(SD_OPERATION_CONDITION_POLL_INTERVAL);
                 continue;
             }
 
@@ -1801,12 +2021,14 @@ impl SdCard {
                 return Ok(());
             }
 
-            i -= 1;
-            sleep(Duration::from_millis(10));
+            if expired {
+                break;
+            }
+            sleep(SD_OPERATION_CONDITION_POLL_INTERVAL);
         }
 
         info!("\r\nError: send ACMD41 timeout\r\n");
-        Ok(())
+        Err(MCIHostError::Timeout)
     }
 
     /// ACMD 51
@@ -1830,19 +2052,7 @@ impl SdCard {
 
         data.block_size_set(8);
         data.block_count_set(1);
-        // We implemented 'Drop' for PoolBuffer, so we don't need to dealloc this rx_buf
-        let rx_buf = match PoolBuffer::new(8 * size_of::<u32>(), size_of::<u32>()) {
-            Err(e) => panic!("ACMD51 allocate rx_buf failed, err {:?}", e),
-            Ok(buf) => {
-                warn!(
-                    "ACMD51: alloc rx_buf at {:p}, size {}",
-                    buf.addr().as_ptr(),
-                    buf.size()
-                );
-                buf
-            }
-        };
-        data.rx_data_set(Some(rx_buf.to_vec::<u32>().unwrap()));
+        data.rx_data_alloc(2)?;
 
         let mut content = MCIHostTransfer::new();
         content.set_cmd(Some(command));
@@ -1876,15 +2086,15 @@ impl SdCard {
 impl SdCard {
     fn decode_cid(&mut self) {
         let cid = &mut self.cid;
-        let rawcid = match self.base.internal_buffer.to_vec_in_len::<u32>(4) {
-            Err(e) => {
+        let rawcid = match self.base.internal_buffer.get(..4) {
+            None => {
                 error!(
-                    "Construct Vec<u32> from internal_buffer failed! err: {:?}",
-                    e
+                    "Construct Vec<u32> from internal_buffer failed! len: {}",
+                    self.base.internal_buffer.len()
                 );
                 panic!();
             }
-            Ok(rawcid) => rawcid,
+            Some(rawcid) => rawcid.to_vec(),
         };
 
         cid.manufacturer_id = ((rawcid[3] & 0xFF000000) >> 24) as u8;
@@ -1904,15 +2114,15 @@ impl SdCard {
 
     fn decode_csd(&mut self) {
         let csd = &mut self.csd;
-        let rawcsd = match self.base.internal_buffer.to_vec_in_len::<u32>(4) {
-            Err(e) => {
+        let rawcsd = match self.base.internal_buffer.get(..4) {
+            None => {
                 error!(
-                    "Construct Vec<u32> from internal_buffer failed! err: {:?}",
-                    e
+                    "Construct Vec<u32> from internal_buffer failed! len: {}",
+                    self.base.internal_buffer.len()
                 );
                 panic!();
             }
-            Ok(rawcsd) => rawcsd,
+            Some(rawcsd) => rawcsd.to_vec(),
         };
 
         csd.csd_structure = ((rawcsd[3] & 0xC0000000) >> 30) as u8;
@@ -1999,9 +2209,12 @@ impl SdCard {
             "Card block count {}, block size {}",
             self.block_count, self.base.block_size
         );
+
+        /* keep raw csd for CMD27 */
+        self.raw_csd.copy_from_slice(&rawcsd[..4]);
     }
 
-    fn decode_scr(&mut self, rawscr: &Vec<u32>) {
+    fn decode_scr(&mut self, rawscr: &[u32]) {
         let scr = &mut self.scr;
 
         scr.scr_structure = ((rawscr[0] & 0xF0000000) >> 28) as u8;
@@ -2051,7 +2264,7 @@ impl SdCard {
         }
     }
 
-    fn decode_status(&mut self, status: &Vec<u32>) {
+    fn decode_status(&mut self, status: &[u32]) {
         self.stat.bus_width = ((status[0] & 0xC0000000) >> 30) as u8; /* 511-510 */
         self.stat.secure_mode = ((status[0] & 0x20000000) >> 29) as u8; /* 509 */
         self.stat.card_type = (status[0] & 0x0000FFFF) as u16; /* 495-480 */
@@ -0,0 +1,125 @@
+//! SD 卡非阻塞读, 发出命令后立即返回, 由调用者轮询完成
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+use log::*;
+
+use super::consts::*;
+use super::{SdCard, SdPowerState};
+use crate::mci_host::err::{MCIHostError, MCIHostStatus};
+use crate::mci_host::mci_host_transfer::MCIHostTransfer;
+use crate::now;
+
+/// 已发出的非阻塞读
+pub(crate) struct SdPendingRead {
+    content: MCIHostTransfer,
+    bytes: u32,
+    start: Duration,
+    done: bool,
+}
+
+impl SdCard {
+    /// Issue a read of `block_count` blocks and return without waiting,
+    /// drive it with [`SdCard::poll`] and get data by [`SdCard::read_data_take`]
+    pub fn start_read(&mut self, start_block: u32, block_count: u32) -> MCIHostStatus {
+        if self.is_locked {
+            return Err(MCIHostError::CardLocked);
+        }
+        if self.pending_read.is_some() {
+            return Err(MCIHostError::Busy);
+        }
+        if self.power_state != SdPowerState::Active {
+            return Err(MCIHostError::CardSuspended);
+        }
+
+        let block_size = self.base.block_size;
+        {
+            let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+            if block_count == 0 || block_count > host.max_block_count.load(Ordering::Relaxed) {
+                error!(
+                    "Error: non-blocking read {} blocks, 1 ~ {} is supported",
+                    block_count,
+                    host.max_block_count.load(Ordering::Relaxed)
+                );
+                return Err(MCIHostError::InvalidArgument);
+            }
+        }
+        if start_block
+            .checked_add(block_count)
+            .is_none_or(|end| end > self.block_count)
+        {
+            return Err(MCIHostError::OutOfRange);
+        }
+
+        let start = now();
+
+        /* read command are not allowed while card is programming */
+        if Err(MCIHostError::CardStatusIdle)
+            != self.polling_card_status_busy(SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT)
+        {
+            info!("Error : read failed with wrong card busy\r\n");
+            return Err(MCIHostError::PollingCardIdleFailed);
+        }
+
+        let mut content = self.read_content_build(start_block, block_size, block_count)?;
+
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        if let Err(err) = host.dev.transfer_start_nb(&mut content, host) {
+            info!("\r\nError: start read failed with host error {:?}\r\n", err);
+            return Err(err);
+        }
+
+        self.pending_read = Some(SdPendingRead {
+            content,
+            bytes: block_size * block_count,
+            start,
+            done: false,
+        });
+        Ok(())
+    }
+
+    /// Check the read issued by [`SdCard::start_read`] once, never blocks
+    pub fn poll(&mut self) -> nb::Result<(), MCIHostError> {
+        let pending = self
+            .pending_read
+            .as_mut()
+            .ok_or(MCIHostError::NoTransferInProgress)?;
+        if pending.done {
+            return Ok(());
+        }
+
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        match host.dev.transfer_poll(&mut pending.content, host) {
+            Ok(()) => {
+                pending.done = true;
+                self.stats.read_record(pending.bytes, now() - pending.start);
+                Ok(())
+            }
+            Err(nb::Error::WouldBlock) => Err(nb::Error::WouldBlock),
+            Err(nb::Error::Other(err)) => {
+                info!("\r\nError: read failed with host error {:?}\r\n", err);
+                self.pending_read = None;
+                /* 与阻塞读相同, 复位 FIFO/IDMAC 并中止传输, 卡才能接收下一条命令 */
+                if let Err(abort_err) = self.transfer_abort(true) {
+                    warn!("abort read failed {:?}", abort_err);
+                }
+                Err(nb::Error::Other(err))
+            }
+        }
+    }
+
+    /// Data of the finished non-blocking read, `None` if the read is not finished yet
+    pub fn read_data_take(&mut self) -> Option<Vec<u32>> {
+        if !self.pending_read.as_ref()?.done {
+            return None;
+        }
+
+        let mut pending = self.pending_read.take()?;
+        pending
+            .content
+            .data_mut()?
+            .rx_data_take()
+            .map(|rx_data| rx_data.to_vec())
+    }
+}
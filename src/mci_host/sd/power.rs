@@ -0,0 +1,134 @@
+//! SD 卡挂起/恢复
+use log::*;
+
+use super::consts::*;
+use super::SdCard;
+use crate::mci_host::constants::*;
+use crate::mci_host::err::{MCIHostError, MCIHostStatus};
+
+/// 卡的电源状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SdPowerState {
+    Active,
+    /// 卡取消选中且时钟关闭, 仍保持供电
+    Suspended,
+    /// 卡已断电, 恢复时需要重新初始化
+    PoweredOff,
+}
+
+impl SdCard {
+    pub fn power_state(&self) -> SdPowerState {
+        self.power_state
+    }
+
+    /// Deselect the card and gate the bus clock, also cut card power if `power_off`
+    pub fn suspend(&mut self, power_off: bool) -> MCIHostStatus {
+        match self.power_state {
+            SdPowerState::PoweredOff => return Ok(()),
+            SdPowerState::Suspended if !power_off => return Ok(()),
+            _ => {}
+        }
+        if self.pending_read.is_some() {
+            return Err(MCIHostError::Busy);
+        }
+
+        if self.power_state == SdPowerState::Active {
+            /* wait for programming finished before card leaves transfer state */
+            if Err(MCIHostError::CardStatusIdle)
+                != self.polling_card_status_busy(SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT)
+            {
+                error!("Error: suspend failed, card status busy");
+                return Err(MCIHostError::PollingCardIdleFailed);
+            }
+
+            /* CMD7 with RCA 0, card goes to stand-by state */
+            if self.card_select(false).is_err() {
+                return Err(MCIHostError::DeselectCardFailed);
+            }
+
+            let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+            host.dev.card_clock_set(0, host);
+            self.power_state = SdPowerState::Suspended;
+        }
+
+        if power_off {
+            self.card_power_set(false)?;
+            self.power_state = SdPowerState::PoweredOff;
+        }
+
+        info!("card suspended, state {:?}", self.power_state);
+        Ok(())
+    }
+
+    /// Bring the card back to transfer state, restores voltage, bus width, timing
+    /// and tuning if card kept power, otherwise re-initializes the card
+    pub fn resume(&mut self) -> MCIHostStatus {
+        if self.power_state == SdPowerState::Active {
+            return Ok(());
+        }
+
+        if !self.card_is_inserted() {
+            info!("\r\nError: card removed while suspended\r\n");
+            return Err(MCIHostError::CardDetectFailed);
+        }
+
+        if self.power_state == SdPowerState::Suspended && self.state_restore().is_ok() {
+            self.power_state = SdPowerState::Active;
+            info!("card resumed");
+            return Ok(());
+        }
+
+        /* card lost power or state, go through identification again */
+        warn!("card state lost, re-initialize card");
+        let serial_number = self.cid.serial_number;
+        if let Err(err) = self.card_init() {
+            warn!("SD card init failed !!! {:?}", err);
+            return Err(MCIHostError::CardInitFailed);
+        }
+        /* 换了卡时保持挂起状态, 避免调用者把数据写到另一张卡上 */
+        if self.cid.serial_number != serial_number {
+            info!(
+                "\r\nError: card changed while suspended, serial number 0x{:x} -> 0x{:x}\r\n",
+                serial_number, self.cid.serial_number
+            );
+            return Err(MCIHostError::CardChanged);
+        }
+
+        self.power_state = SdPowerState::Active;
+        info!("card resumed by re-initialization");
+        Ok(())
+    }
+
+    /// 卡仍处于 stand-by 状态, 重新配置主机侧的电压, 位宽和时钟后选中卡
+    fn state_restore(&mut self) -> MCIHostStatus {
+        if self.operation_voltage == MCIHostOperationVoltage::Voltage180V {
+            self.switch_io_voltage(MCIHostOperationVoltage::Voltage180V)?;
+        }
+
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        host.dev
+            .enable_ddr_mode(self.current_timing == SdTimingMode::DDR50Mode, 0);
+        host.dev.card_bus_width_set(self.bus_width);
+        self.base.bus_clk_hz = host.dev.card_clock_set(self.base.bus_clk_hz, host);
+
+        self.card_select(true)?;
+
+        /* sampling point may drift while clock is gated */
+        if self.current_timing == SdTimingMode::SDR104Mode
+            || self.current_timing == SdTimingMode::SDR50Mode
+        {
+            self.execute_tuning()?;
+        }
+
+        let status = self.card_status_get()?;
+        if MCIHostCurrentState::current_state(status) != MCIHostCurrentState::Transfer {
+            info!(
+                "\r\nError: card not in transfer state after resume, status 0x{:x}\r\n",
+                status
+            );
+            return Err(MCIHostError::SelectCardFailed);
+        }
+
+        Ok(())
+    }
+}
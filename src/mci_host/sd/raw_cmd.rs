@@ -0,0 +1,253 @@
+//! 任意命令透传, 类似 Linux MMC_IOC_CMD, 用于产测和厂商命令
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+use log::*;
+
+use super::consts::*;
+use super::{SdCard, SdPowerState};
+use crate::mci_host::constants::*;
+use crate::mci_host::err::{MCIHostError, MCIHostStatus};
+use crate::mci_host::mci_host_transfer::{MCIHostCmd, MCIHostData, MCIHostTransfer};
+
+/// 透传命令的数据阶段
+#[derive(Debug, Clone)]
+pub enum SdRawData {
+    /// 从卡读取 `block_count` 个 `block_size` 字节的块
+    Read { block_size: u32, block_count: u32 },
+    /// 向卡写入 `data`, 长度为 `block_size * block_count` 字节
+    Write {
+        block_size: u32,
+        block_count: u32,
+        data: Vec<u32>,
+    },
+}
+
+/// 透传命令
+#[derive(Debug, Clone)]
+pub struct SdRawCmd {
+    pub index: u32,
+    pub argument: u32,
+    pub response_type: MCIHostResponseType,
+    /// 先发送 CMD55, 作为 ACMD 发出
+    pub is_app_cmd: bool,
+    pub data: Option<SdRawData>,
+    /// 命令完成后用 CMD13 轮询直到卡退出编程状态, `None` 时不等待
+    pub busy_timeout: Option<Duration>,
+}
+
+/// 透传命令的结果
+#[derive(Debug, Clone)]
+pub struct SdRawResponse {
+    /// 原始响应, R2 占用全部 4 个字, 其余只有第一个字有效
+    pub response: [u32; 4],
+    /// R1/R1b 响应中的错误位, 其他响应类型为空
+    pub error_flags: MCIHostCardStatusFlag,
+    /// 读数据阶段收到的数据
+    pub data: Option<Vec<u32>>,
+}
+
+impl SdRawCmd {
+    /// Command without data phase
+    pub fn new(index: u32, argument: u32, response_type: MCIHostResponseType) -> Self {
+        SdRawCmd {
+            index,
+            argument,
+            response_type,
+            is_app_cmd: false,
+            data: None,
+            busy_timeout: None,
+        }
+    }
+}
+
+impl SdCard {
+    /// Send an arbitrary command, the card status errors are returned in
+    /// [`SdRawResponse::error_flags`] instead of an error. Commands that would change
+    /// state the driver tracks (identification, bus width, CMD6 switch, CMD42, block
+    /// length) are rejected with [`MCIHostError::InvalidArgument`]
+    pub fn raw_cmd_send(&mut self, raw: SdRawCmd) -> MCIHostStatus<SdRawResponse> {
+        if self.power_state != SdPowerState::Active {
+            return Err(MCIHostError::CardSuspended);
+        }
+        if self.pending_read.is_some() {
+            return Err(MCIHostError::Busy);
+        }
+        self.raw_cmd_check(&raw)?;
+
+        let mut command = MCIHostCmd::new();
+        command.index_set(raw.index);
+        command.argument_set(raw.argument);
+        command.response_type_set(raw.response_type);
+
+        let mut content = MCIHostTransfer::new();
+        content.set_cmd(Some(command));
+        if let Some(raw_data) = raw.data {
+            content.set_data(Some(self.raw_data_build(raw.index, raw_data)?));
+        }
+        let has_data = content.data().is_some();
+
+        if raw.is_app_cmd
+            && self
+                .application_cmd_send(self.base.relative_address)
+                .is_err()
+        {
+            info!("\r\nError: send CMD55 before ACMD{} failed\r\n", raw.index);
+            return Err(MCIHostError::SendApplicationCommandFailed);
+        }
+
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        if let Err(err) = host.dev.transfer_function(&mut content, host) {
+            info!(
+                "\r\nError: raw CMD{} failed with host error {:?}\r\n",
+                raw.index, err
+            );
+            /* 让卡回到可以接收下一条命令的状态 */
+            let _ = self.transfer_abort(has_data);
+            return Err(err);
+        }
+
+        if let Some(timeout) = raw.busy_timeout {
+            if Err(MCIHostError::CardStatusIdle) != self.polling_card_status_busy(timeout) {
+                return Err(MCIHostError::WaitWriteCompleteFailed);
+            }
+        }
+
+        let response = *content.cmd().unwrap().response();
+        let error_flags = match raw.response_type {
+            MCIHostResponseType::R1 | MCIHostResponseType::R1b => {
+                MCIHostCardStatusFlag::from_bits_truncate(response[0])
+                    & MCIHostCardStatusFlag::ALL_ERROR_FLAG
+            }
+            _ => MCIHostCardStatusFlag::empty(),
+        };
+        let data = content
+            .data_mut()
+            .and_then(|data| data.rx_data_take())
+            .map(|rx_data| rx_data.to_vec());
+
+        Ok(SdRawResponse {
+            response,
+            error_flags,
+            data,
+        })
+    }
+
+    fn raw_cmd_check(&self, raw: &SdRawCmd) -> MCIHostStatus {
+        if raw.index > 63 {
+            return Err(MCIHostError::InvalidArgument);
+        }
+
+        /* 这些命令会改变卡的识别状态或 RCA, 驱动记录的状态随之失效 */
+        if !raw.is_app_cmd {
+            let forbidden = [
+                MCIHostCommonCmd::GoIdleState as u32,
+                MCIHostCommonCmd::AllSendCid as u32,
+                SdCmd::SendRelativeAddress as u32,
+                MCIHostCommonCmd::SelectCard as u32,
+                SdCmd::VoltageSwitch as u32,
+                MCIHostCommonCmd::GoInactiveState as u32,
+                MCIHostCommonCmd::ApplicationCommand as u32,
+                /* 锁卡状态由 lock/unlock 接口维护 */
+                MCIHostCommonCmd::LockUnlock as u32,
+            ];
+            if forbidden.contains(&raw.index) {
+                info!(
+                    "\r\nError: CMD{} changes card state, not allowed for raw command\r\n",
+                    raw.index
+                );
+                return Err(MCIHostError::InvalidArgument);
+            }
+        }
+
+        /* 总线宽度由驱动记录, 恢复和降级时按记录重新配置 */
+        if raw.is_app_cmd && raw.index == SdAppCmd::SetBusWdith as u32 {
+            info!("\r\nError: ACMD6 changes bus width, not allowed for raw command\r\n");
+            return Err(MCIHostError::InvalidArgument);
+        }
+
+        /* CMD6 切换模式会改变时序、驱动强度和电流限制, 只允许查询模式 */
+        if !raw.is_app_cmd && raw.index == SdCmd::Switch as u32 && raw.argument & (1 << 31) != 0 {
+            info!("\r\nError: CMD6 switch mode not allowed for raw command\r\n");
+            return Err(MCIHostError::InvalidArgument);
+        }
+
+        /* 读写接口不再发送 CMD16, 块长固定为 512 字节 */
+        if !raw.is_app_cmd
+            && raw.index == MCIHostCommonCmd::SetBlockLength as u32
+            && raw.argument != SD_BLOCK_SIZE as u32
+        {
+            info!("\r\nError: block length is fixed to 512\r\n");
+            return Err(MCIHostError::InvalidArgument);
+        }
+
+        let (block_size, block_count, tx_len) = match &raw.data {
+            None => return Ok(()),
+            Some(SdRawData::Read {
+                block_size,
+                block_count,
+            }) => (*block_size, *block_count, None),
+            Some(SdRawData::Write {
+                block_size,
+                block_count,
+                data,
+            }) => (*block_size, *block_count, Some(data.len())),
+        };
+
+        /* 数据按字搬运, 块大小必须是 4 字节的整数倍 */
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        if block_size == 0
+            || block_size % 4 != 0
+            || block_size > MCI_HOST_DEFAULT_BLOCK_SIZE
+            || block_count == 0
+            || block_count > host.max_block_count.load(Ordering::Relaxed)
+        {
+            info!(
+                "\r\nError: raw CMD{} data of {} blocks x {} bytes not supported\r\n",
+                raw.index, block_count, block_size
+            );
+            return Err(MCIHostError::InvalidArgument);
+        }
+        if tx_len.is_some_and(|len| len * 4 != (block_size * block_count) as usize) {
+            return Err(MCIHostError::InvalidArgument);
+        }
+        if raw.response_type == MCIHostResponseType::None {
+            return Err(MCIHostError::InvalidArgument);
+        }
+
+        Ok(())
+    }
+
+    fn raw_data_build(&self, index: u32, raw_data: SdRawData) -> MCIHostStatus<MCIHostData> {
+        let mut data = MCIHostData::new();
+        let is_write = matches!(raw_data, SdRawData::Write { .. });
+        match raw_data {
+            SdRawData::Read {
+                block_size,
+                block_count,
+            } => {
+                data.block_size_set(block_size as usize);
+                data.block_count_set(block_count);
+                data.rx_data_alloc((block_size * block_count / 4) as usize)?;
+            }
+            SdRawData::Write {
+                block_size,
+                block_count,
+                data: tx_data,
+            } => {
+                data.block_size_set(block_size as usize);
+                data.block_count_set(block_count);
+                data.tx_data_copy(&tx_data)?;
+            }
+        }
+        data.timeout_set(self.data_timeout(data.block_size() as u32, data.block_count(), is_write));
+
+        if index == MCIHostCommonCmd::ReadMultipleBlock as u32
+            || index == MCIHostCommonCmd::WriteMultipleBlock as u32
+        {
+            self.multi_block_stop_set(&mut data);
+        }
+        Ok(data)
+    }
+}
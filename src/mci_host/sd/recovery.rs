@@ -0,0 +1,163 @@
+//! SD 卡传输失败后的逐级恢复: 复位 FIFO/IDMAC, 重试, 重新调谐, 降低时序, 重新初始化
+use log::*;
+
+use super::consts::*;
+use super::SdCard;
+use crate::mci_host::constants::*;
+use crate::mci_host::err::{MCIHostError, MCIHostStatus};
+use crate::mci_host::mci_host_config::MCIHostRecoveryPolicy;
+
+/// 重试用尽后下一级的恢复手段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SdRecoveryStage {
+    Retune,
+    /// 每次失败降一级, 直到 25MHz 1 线
+    Downgrade,
+    Reinit,
+    Exhausted,
+}
+
+impl SdCard {
+    pub fn recovery_policy(&self) -> MCIHostRecoveryPolicy {
+        match self.base.host.as_ref() {
+            Some(host) => host.config.recovery,
+            None => MCIHostRecoveryPolicy::default(),
+        }
+    }
+
+    /// Set how transfers of this host recover from failures
+    pub fn recovery_policy_set(&mut self, policy: MCIHostRecoveryPolicy) -> MCIHostStatus {
+        let host = self.base.host.as_mut().ok_or(MCIHostError::HostNotReady)?;
+        host.config.recovery = policy;
+        Ok(())
+    }
+
+    /// Make the next `count` data transfers fail with `err` before they are issued,
+    /// for testing how transfers recover, 0 cancels the pending faults
+    #[cfg(feature = "fault-inject")]
+    pub fn fault_inject(&mut self, err: MCIHostError, count: u32) -> MCIHostStatus {
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        host.dev.fault_inject_set(err, count);
+        Ok(())
+    }
+
+    /// 复位 FIFO/IDMAC, 数据传输失败时再发送 CMD12 中止并等待卡空闲
+    pub(crate) fn transfer_abort(&mut self, has_data: bool) -> MCIHostStatus {
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        /* 失败的传输会在 FIFO 和 IDMAC 中留下残余数据和描述符状态 */
+        host.dev.reset()?;
+
+        if has_data {
+            self.stats.aborts += 1;
+            let _ = self.transmission_stop();
+            /* polling card status until it is ready for next data transfer, otherwise the
+             * retry transfer will fail again */
+            if Err(MCIHostError::CardStatusIdle)
+                != self.polling_card_status_busy(SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT)
+            {
+                return Err(MCIHostError::PollingCardIdleFailed);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 执行 `stage` 对应的恢复手段, 不可用或失败时继续升级, 全部用尽返回 false
+    pub(crate) fn recovery_escalate(
+        &mut self,
+        stage: &mut SdRecoveryStage,
+        policy: &MCIHostRecoveryPolicy,
+    ) -> bool {
+        self.in_recovery = true;
+        let recovered = loop {
+            match *stage {
+                SdRecoveryStage::Retune => {
+                    *stage = SdRecoveryStage::Downgrade;
+                    /* Sampling clock tuning is required for UHS104 host and optional for UHS50 host */
+                    if policy.retune
+                        && (self.current_timing == SdTimingMode::SDR104Mode
+                            || self.current_timing == SdTimingMode::SDR50Mode)
+                    {
+                        self.stats.retunes += 1;
+                        match self.execute_tuning() {
+                            Ok(()) => {
+                                info!("recovery: retuning successfully");
+                                break true;
+                            }
+                            Err(err) => warn!("recovery: retuning failed {:?}", err),
+                        }
+                    }
+                }
+                SdRecoveryStage::Downgrade => {
+                    if policy.downgrade && self.timing_downgrade().is_ok() {
+                        self.stats.downgrades += 1;
+                        break true;
+                    }
+                    *stage = SdRecoveryStage::Reinit;
+                }
+                SdRecoveryStage::Reinit => {
+                    *stage = SdRecoveryStage::Exhausted;
+                    if policy.reinit {
+                        warn!("recovery: re-initialize card");
+                        self.stats.card_resets += 1;
+                        match self.card_init_proc() {
+                            Ok(()) => break true,
+                            Err(err) => warn!("recovery: re-initialize failed {:?}", err),
+                        }
+                    }
+                }
+                SdRecoveryStage::Exhausted => break false,
+            }
+        };
+        self.in_recovery = false;
+        recovered
+    }
+
+    /// 降低一级总线时钟, 已是 25MHz 时退回 1 线模式, DDR50 先退回 SDR25
+    fn timing_downgrade(&mut self) -> MCIHostStatus {
+        /* DDR50 只能工作在 4 线模式, 降级时先退出 DDR */
+        if self.current_timing == SdTimingMode::DDR50Mode {
+            /* SDR25 下电流限制回到 200mA */
+            self.uhs_drive_select(SdTimingMode::SDR25HighSpeedMode)?;
+            self.func_select(
+                SdGroupNum::TimingMode,
+                SdTimingFuncNum::SDR25HighSpeed as u32,
+            )?;
+            let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+            /* 频率不变, 关闭 DDR 后时钟按 SDR 时序重新配置 */
+            host.dev.enable_ddr_mode(false, 0);
+            self.current_timing = SdTimingMode::SDR25HighSpeedMode;
+            self.base.bus_clk_hz = host.dev.card_clock_set(SD_CLOCK_50MHZ, host);
+            warn!("recovery: bus timing downgrade DDR50 -> SDR25");
+            return Ok(());
+        }
+
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        for clock in [SD_CLOCK_100MHZ, SD_CLOCK_50MHZ, SD_CLOCK_25MHZ] {
+            if clock >= self.base.bus_clk_hz {
+                continue;
+            }
+            let actual_clock = host.dev.card_clock_set(clock, host);
+            if actual_clock < self.base.bus_clk_hz {
+                warn!(
+                    "recovery: bus clock downgrade {} -> {}",
+                    self.base.bus_clk_hz, actual_clock
+                );
+                self.base.bus_clk_hz = actual_clock;
+                return Ok(());
+            }
+        }
+
+        if self.bus_width == MCIHostBusWdith::Bit4 {
+            self.data_bus_width_set(MCIHostBusWdith::Bit1)?;
+            let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+            host.dev.card_bus_width_set(MCIHostBusWdith::Bit1);
+            /* 之后的恢复和 resume 都按 1 线配置 */
+            self.bus_width = MCIHostBusWdith::Bit1;
+            warn!("recovery: bus width downgrade to 1 bit");
+            return Ok(());
+        }
+
+        Err(MCIHostError::NotSupportYet)
+    }
+}
@@ -0,0 +1,151 @@
+//! SD 卡分散/聚集 DMA 读写, 数据直接在调用者提供的物理段中收发, 不经过内部缓冲区
+use core::sync::atomic::Ordering;
+
+use log::*;
+
+use super::consts::*;
+use super::{SdCard, SdPowerState};
+use crate::mci::mci_dma::MCIDmaSegment;
+use crate::mci_host::constants::*;
+use crate::mci_host::err::{MCIHostError, MCIHostStatus};
+use crate::mci_host::mci_host_transfer::{MCIHostCmd, MCIHostData, MCIHostTransfer};
+use crate::now;
+
+impl SdCard {
+    /// Read blocks into physical segments, caller should invalidate the segments after read
+    pub fn read_blocks_sg(
+        &mut self,
+        segments: &[MCIDmaSegment],
+        start_block: u32,
+        block_count: u32,
+    ) -> MCIHostStatus {
+        if self.is_locked {
+            return Err(MCIHostError::CardLocked);
+        }
+        if self.power_state != SdPowerState::Active {
+            return Err(MCIHostError::CardSuspended);
+        }
+
+        self.sg_transfer(segments, start_block, block_count, false)
+    }
+
+    /// Write blocks from physical segments, caller should flush the segments before write
+    pub fn write_blocks_sg(
+        &mut self,
+        segments: &[MCIDmaSegment],
+        start_block: u32,
+        block_count: u32,
+    ) -> MCIHostStatus {
+        if self.is_locked {
+            return Err(MCIHostError::CardLocked);
+        }
+        if self.power_state != SdPowerState::Active {
+            return Err(MCIHostError::CardSuspended);
+        }
+        if self.is_read_only() {
+            return Err(MCIHostError::ReadOnly);
+        }
+
+        self.sg_transfer(segments, start_block, block_count, true)
+    }
+
+    /// CMD 17/18/24/25
+    fn sg_transfer(
+        &mut self,
+        segments: &[MCIDmaSegment],
+        start_block: u32,
+        block_count: u32,
+        is_write: bool,
+    ) -> MCIHostStatus {
+        let block_size = self.base.block_size;
+        {
+            let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+            if !host.config.enable_dma {
+                error!("Error: scatter/gather transfer needs DMA mode");
+                return Err(MCIHostError::HostNotSupport);
+            }
+            if block_count == 0 || block_count > host.max_block_count.load(Ordering::Relaxed) {
+                error!(
+                    "Error: scatter/gather transfer {} blocks, 1 ~ {} is supported",
+                    block_count,
+                    host.max_block_count.load(Ordering::Relaxed)
+                );
+                return Err(MCIHostError::InvalidArgument);
+            }
+        }
+        if segments.iter().map(|seg| seg.len as u64).sum::<u64>()
+            != block_size as u64 * block_count as u64
+        {
+            error!(
+                "Error: scatter/gather segments do not match {} blocks",
+                block_count
+            );
+            return Err(MCIHostError::InvalidArgument);
+        }
+        if start_block
+            .checked_add(block_count)
+            .is_none_or(|end| end > self.block_count)
+        {
+            return Err(MCIHostError::OutOfRange);
+        }
+
+        let start = now();
+
+        if Err(MCIHostError::CardStatusIdle)
+            != self.polling_card_status_busy(SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT)
+        {
+            error!("Error : scatter/gather transfer failed with wrong card busy\r\n");
+            return Err(MCIHostError::PollingCardIdleFailed);
+        }
+
+        let mut command = MCIHostCmd::new();
+        command.index_set(match (is_write, block_count == 1) {
+            (false, true) => MCIHostCommonCmd::ReadSingleBlock as u32,
+            (false, false) => MCIHostCommonCmd::ReadMultipleBlock as u32,
+            (true, true) => MCIHostCommonCmd::WriteSingleBlock as u32,
+            (true, false) => MCIHostCommonCmd::WriteMultipleBlock as u32,
+        });
+        command.argument_set(if self.flags.contains(SdCardFlag::SupportHighCapacity) {
+            start_block
+        } else {
+            start_block * block_size
+        });
+        command.response_type_set(MCIHostResponseType::R1);
+        command.response_error_flags_set(MCIHostCardStatusFlag::ALL_ERROR_FLAG);
+
+        let mut data = MCIHostData::new();
+        data.block_size_set(block_size as usize);
+        data.block_count_set(block_count);
+        data.timeout_set(self.data_timeout(block_size, block_count, is_write));
+        self.multi_block_stop_set(&mut data);
+        if is_write {
+            data.tx_sg_set(Some(segments.to_vec()));
+        } else {
+            data.rx_sg_set(Some(segments.to_vec()));
+        }
+
+        let mut content = MCIHostTransfer::new();
+        content.set_cmd(Some(command));
+        content.set_data(Some(data));
+
+        self.transfer(&mut content, 3)?;
+
+        let len = block_size * block_count;
+        if is_write {
+            let mut written_blocks = block_count;
+            self.write_successful_block_send(&mut written_blocks)?;
+            if written_blocks != block_count {
+                error!(
+                    "Error: {} of {} blocks written",
+                    written_blocks, block_count
+                );
+                return Err(MCIHostError::TransferFailed);
+            }
+            self.stats.write_record(len, now() - start);
+        } else {
+            self.stats.read_record(len, now() - start);
+        }
+
+        Ok(())
+    }
+}
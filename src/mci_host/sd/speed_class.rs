@@ -0,0 +1,198 @@
+//! 速度等级控制 (CMD20), 录制期间按 AU 边界切分顺序写, 卡才能保证其速度等级
+use log::*;
+
+use super::consts::*;
+use super::{SdCard, SdPowerState};
+use crate::mci_host::constants::*;
+use crate::mci_host::err::{MCIHostError, MCIHostStatus};
+use crate::mci_host::mci_host_transfer::{MCIHostCmd, MCIHostTransfer};
+
+/// 录制状态, 记录下一次流写入的块地址
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SdRecording {
+    next_block: u32,
+    au_blocks: u32,
+}
+
+/// SD 状态中 AU_SIZE/UHS_AU_SIZE 编码对应的 AU 块数, 0 表示未定义
+fn au_size_to_blocks(au_size: u8) -> u32 {
+    const KB: u32 = 1024;
+    const MB: u32 = 1024 * KB;
+    let bytes = match au_size {
+        1..=9 => (16 * KB) << (au_size - 1),
+        0xA => 8 * MB,
+        0xB => 12 * MB,
+        0xC => 16 * MB,
+        0xD => 24 * MB,
+        0xE => 32 * MB,
+        0xF => 64 * MB,
+        _ => 0,
+    };
+    bytes / MCI_HOST_DEFAULT_BLOCK_SIZE
+}
+
+impl SdCard {
+    /// Allocation unit size in blocks, UHS cards report a separate AU size for UHS modes
+    pub fn au_size_blocks(&self) -> u32 {
+        let uhs_timing = matches!(
+            self.current_timing,
+            SdTimingMode::SDR50Mode | SdTimingMode::SDR104Mode | SdTimingMode::DDR50Mode
+        );
+        if uhs_timing && self.stat.uhs_au_size != 0 {
+            au_size_to_blocks(self.stat.uhs_au_size)
+        } else {
+            au_size_to_blocks(self.stat.au_size)
+        }
+    }
+
+    /// Block the next [`SdCard::write_stream`] writes to, `None` when not recording
+    pub fn recording_position(&self) -> Option<u32> {
+        self.recording.map(|recording| recording.next_block)
+    }
+
+    /// Start speed class recording, `start_block` is rounded up to the next AU boundary
+    /// and the aligned block is returned
+    pub fn recording_start(&mut self, start_block: u32) -> MCIHostStatus<u32> {
+        let au_blocks = self.au_size_blocks();
+        if au_blocks == 0 {
+            info!("\r\nError: card reports no AU size\r\n");
+            return Err(MCIHostError::CardNotSupport);
+        }
+
+        let aligned = start_block.div_ceil(au_blocks) * au_blocks;
+        if aligned >= self.block_count {
+            return Err(MCIHostError::InvalidArgument);
+        }
+
+        self.speed_class_control(SdSpeedClassCtrl::StartRecording)?;
+        self.recording = Some(SdRecording {
+            next_block: aligned,
+            au_blocks,
+        });
+        debug!(
+            "recording started at block {}, AU {} blocks",
+            aligned, au_blocks
+        );
+
+        Ok(aligned)
+    }
+
+    /// Stop recording, the card has no command for it, only waits for the last write
+    /// to finish and drops the stream position
+    pub fn recording_stop(&mut self) -> MCIHostStatus {
+        if self.recording.take().is_none() {
+            return Ok(());
+        }
+
+        if Err(MCIHostError::CardStatusIdle)
+            != self.polling_card_status_busy(SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT)
+        {
+            return Err(MCIHostError::WaitWriteCompleteFailed);
+        }
+        Ok(())
+    }
+
+    /// Create a directory entry while recording, `buffer` holds the one-block entry
+    /// written to `block` right after CMD20
+    pub fn dir_create(&mut self, buffer: &[u32], block: u32) -> MCIHostStatus {
+        self.dir_write(SdSpeedClassCtrl::CreateDir, buffer, block)
+    }
+
+    /// Update a directory entry while recording, e.g. the file size of the stream
+    pub fn dir_update(&mut self, buffer: &[u32], block: u32) -> MCIHostStatus {
+        self.dir_write(SdSpeedClassCtrl::UpdateDir, buffer, block)
+    }
+
+    /// Append `block_count` blocks to the recording stream, writes are split so that
+    /// none of them crosses an AU boundary
+    pub fn write_stream(&mut self, buffer: &[u32], block_count: u32) -> MCIHostStatus {
+        let Some(recording) = self.recording else {
+            info!("\r\nError: write stream without recording started\r\n");
+            return Err(MCIHostError::InvalidArgument);
+        };
+        let words_per_block = (MCI_HOST_DEFAULT_BLOCK_SIZE / 4) as usize;
+        if buffer.len() < block_count as usize * words_per_block
+            || recording.next_block as u64 + block_count as u64 > self.block_count as u64
+        {
+            return Err(MCIHostError::InvalidArgument);
+        }
+
+        let mut written = 0;
+        while written < block_count {
+            let recording = self.recording.ok_or(MCIHostError::RecordingLost)?;
+            let block = recording.next_block;
+            let au_left = recording.au_blocks - block % recording.au_blocks;
+            let count = au_left.min(block_count - written);
+
+            let start = written as usize * words_per_block;
+            let end = start + count as usize * words_per_block;
+            self.write_blocks(&buffer[start..end], block, count)?;
+
+            written += count;
+            /* 写入失败后的恢复流程可能重新初始化卡, 卡已退出录制状态 */
+            let Some(recording) = self.recording.as_mut() else {
+                info!("\r\nError: recording lost after {} blocks\r\n", written);
+                return Err(MCIHostError::RecordingLost);
+            };
+            /* 写成功的部分计入录制位置, 出错后可从断点继续 */
+            recording.next_block = block + count;
+        }
+
+        Ok(())
+    }
+
+    fn dir_write(&mut self, ctrl: SdSpeedClassCtrl, buffer: &[u32], block: u32) -> MCIHostStatus {
+        if self.recording.is_none() {
+            info!("\r\nError: {:?} without recording started\r\n", ctrl);
+            return Err(MCIHostError::InvalidArgument);
+        }
+
+        self.speed_class_control(ctrl)?;
+        self.write_blocks(buffer, block, 1)
+    }
+
+    /// CMD 20
+    fn speed_class_control(&mut self, ctrl: SdSpeedClassCtrl) -> MCIHostStatus {
+        if !self.flags.contains(SdCardFlag::SupportSpeedClassControlCmd) {
+            return Err(MCIHostError::CardNotSupport);
+        }
+        if self.is_locked {
+            return Err(MCIHostError::CardLocked);
+        }
+        if self.power_state != SdPowerState::Active {
+            return Err(MCIHostError::CardSuspended);
+        }
+        if self.is_read_only() {
+            return Err(MCIHostError::ReadOnly);
+        }
+
+        if Err(MCIHostError::CardStatusIdle)
+            != self.polling_card_status_busy(SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT)
+        {
+            return Err(MCIHostError::WaitWriteCompleteFailed);
+        }
+
+        let mut command = MCIHostCmd::new();
+        command.index_set(SdCmd::SpeedClassControl as u32);
+        command.argument_set((ctrl as u32) << 28);
+        command.response_type_set(MCIHostResponseType::R1b);
+        command.response_error_flags_set(MCIHostCardStatusFlag::ALL_ERROR_FLAG);
+
+        let mut content = MCIHostTransfer::new();
+        content.set_cmd(Some(command));
+        if let Err(e) = self.transfer(&mut content, 0) {
+            info!(
+                "\r\nError: send CMD20 {:?} failed with host error {:?}\r\n",
+                ctrl, e
+            );
+            return Err(e);
+        }
+
+        if Err(MCIHostError::CardStatusIdle)
+            != self.polling_card_status_busy(SD_SPEED_CLASS_CONTROL_TIMEOUT)
+        {
+            return Err(MCIHostError::WaitWriteCompleteFailed);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+//! SD 卡读写统计和健康计数
+use core::time::Duration;
+
+use super::SdCard;
+use crate::mci_host::{MCIHostCmdStats, MCIHostLatencyHistogram};
+
+/// 读写传输统计, 耗时包含等待卡空闲和写完成的时间
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SdTransferStats {
+    pub read_ops: u32,
+    pub read_bytes: u64,
+    pub read_time: Duration,
+    pub read_latency: MCIHostLatencyHistogram,
+    pub write_ops: u32,
+    pub write_bytes: u64,
+    pub write_time: Duration,
+    pub write_latency: MCIHostLatencyHistogram,
+    /// 原样重试的次数
+    pub retries: u32,
+    pub retunes: u32,
+    /// 降低总线时钟或位宽的次数
+    pub downgrades: u32,
+    /// 数据传输失败后发送 CMD12 的次数
+    pub aborts: u32,
+    /// 恢复过程中重新初始化卡的次数
+    pub card_resets: u32,
+    /// 控制器层面的命令统计, 在取快照时填入
+    pub cmd: MCIHostCmdStats,
+}
+
+impl SdTransferStats {
+    /// Average read throughput in bytes per second
+    pub fn read_throughput(&self) -> u64 {
+        Self::throughput(self.read_bytes, self.read_time)
+    }
+
+    /// Average write throughput in bytes per second
+    pub fn write_throughput(&self) -> u64 {
+        Self::throughput(self.write_bytes, self.write_time)
+    }
+
+    fn throughput(bytes: u64, time: Duration) -> u64 {
+        match time.as_micros() {
+            0 => 0,
+            us => (bytes as u128 * 1_000_000 / us) as u64,
+        }
+    }
+
+    pub(crate) fn read_record(&mut self, bytes: u32, elapsed: Duration) {
+        self.read_ops += 1;
+        self.read_bytes += bytes as u64;
+        self.read_time += elapsed;
+        self.read_latency.record(elapsed);
+    }
+
+    pub(crate) fn write_record(&mut self, bytes: u32, elapsed: Duration) {
+        self.write_ops += 1;
+        self.write_bytes += bytes as u64;
+        self.write_time += elapsed;
+        self.write_latency.record(elapsed);
+    }
+}
+
+impl SdCard {
+    /// Snapshot of the counters since init or the last [`SdCard::transfer_stats_reset`]
+    pub fn transfer_stats(&self) -> SdTransferStats {
+        let mut stats = self.stats;
+        if let Some(host) = self.base.host.as_ref() {
+            stats.cmd = host.dev.cmd_stats();
+        }
+        stats
+    }
+
+    pub fn transfer_stats_reset(&mut self) {
+        self.stats = SdTransferStats::default();
+        if let Some(host) = self.base.host.as_ref() {
+            host.dev.cmd_stats_reset();
+        }
+    }
+}
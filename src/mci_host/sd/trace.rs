@@ -0,0 +1,54 @@
+//! SD 卡命令跟踪和寄存器快照
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::SdCard;
+use crate::mci::MCIRegisterSnapshot;
+use crate::mci_host::err::{MCIHostError, MCIHostStatus};
+use crate::mci_host::{MCIHostObserver, MCIHostTraceEvent, MCIHostTraceRing};
+
+impl SdCard {
+    /// Set the observer called on every command issue and completion, `None` removes it
+    pub fn trace_observer_set(
+        &mut self,
+        observer: Option<Arc<dyn MCIHostObserver>>,
+    ) -> MCIHostStatus {
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        host.dev.observer_set(observer);
+        Ok(())
+    }
+
+    /// Keep the last `capacity` trace events, 0 stops recording and drops the events
+    pub fn trace_ring_enable(&mut self, capacity: usize) -> MCIHostStatus {
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+        let ring = (capacity != 0).then(|| MCIHostTraceRing::new(capacity));
+        host.dev.trace_ring_set(ring);
+        Ok(())
+    }
+
+    /// Recorded events from the oldest to the newest
+    pub fn trace_events(&self) -> Vec<MCIHostTraceEvent> {
+        self.base
+            .host
+            .as_ref()
+            .map_or(Vec::new(), |host| host.dev.trace_events())
+    }
+
+    /// 打印记录的事件, 用于失败后排查
+    pub fn trace_dump(&self) {
+        if let Some(host) = self.base.host.as_ref() {
+            host.dev.trace_dump();
+        }
+    }
+
+    /// Current controller registers, compare two of them by [`MCIRegisterSnapshot::diff`]
+    pub fn register_snapshot(&self) -> Option<MCIRegisterSnapshot> {
+        let host = self.base.host.as_ref()?;
+        Some(host.dev.register_snapshot())
+    }
+
+    /// 最近一次命令失败时, 复位控制器之前的寄存器
+    pub fn error_registers(&self) -> Option<MCIRegisterSnapshot> {
+        self.base.host.as_ref()?.dev.error_registers()
+    }
+}
@@ -1,6 +1,7 @@
-use super::{consts::SdTimingMode, io_voltage::SdIoVoltage};
+use super::consts::{SdDriverStrength, SdMaxCurrent, SdTimingMode};
+use super::io_voltage::SdIoVoltage;
 use crate::mci_host::mci_host_card_detect::MCIHostCardDetect;
-use alloc::rc::Rc;
+use alloc::sync::Arc;
 
 pub(crate) struct SdUsrParam {
     pub(crate) sd_pwr: Option<SdPwrFn>,
@@ -8,9 +9,13 @@ pub(crate) struct SdUsrParam {
     pub(crate) power_off_delay_ms: u32,
     pub(crate) io_strength: Option<SdIoStrengthFn>,
     pub(crate) io_voltage: Option<SdIoVoltage>,
-    pub(crate) cd: Option<Rc<MCIHostCardDetect>>,
+    pub(crate) cd: Option<Arc<MCIHostCardDetect>>,
     pub(crate) max_freq: u32,
     pub(crate) capability: u32,
+    /// UHS-I 模式下要求卡使用的驱动类型
+    pub(crate) driver_strength: Option<SdDriverStrength>,
+    /// 允许卡使用的最大电流
+    pub(crate) max_current: SdMaxCurrent,
 }
 
 type SdPwrFn = fn(bool);
@@ -27,6 +32,8 @@ impl SdUsrParam {
             cd: None,
             max_freq: 0,
             capability: 0,
+            driver_strength: None,
+            max_current: SdMaxCurrent::Limit200mA,
         }
     }
 }
@@ -0,0 +1,291 @@
+//! SD 卡写保护 (CSD WP 位, 主机 WP 信号, CMD27/28/29/30)
+use alloc::vec::Vec;
+use log::*;
+
+use super::consts::*;
+use super::csd::{CsdFlags, SdCardCmdClass};
+use super::SdCard;
+use crate::mci_host::constants::*;
+use crate::mci_host::err::{MCIHostError, MCIHostStatus};
+use crate::mci_host::mci_host_config::MCIHostCardType;
+use crate::mci_host::mci_host_transfer::{MCIHostCmd, MCIHostData, MCIHostTransfer};
+use crate::tools::crc7;
+
+/* CSD bit 12, TMP_WRITE_PROTECT */
+const SD_CSD_TMP_WRITE_PROTECT: u32 = 1 << 12;
+
+impl SdCard {
+    /// CSD PERM_WRITE_PROTECT, can not be cleared once set
+    pub fn is_permanent_write_protected(&self) -> bool {
+        self.csd.flags & CsdFlags::PERMANENT_WRITE_PROTECT.bits() != 0
+    }
+
+    /// CSD TMP_WRITE_PROTECT
+    pub fn is_temporary_write_protected(&self) -> bool {
+        self.csd.flags & CsdFlags::TEMPORARY_WRITE_PROTECT.bits() != 0
+    }
+
+    /// Host WP signal, only full size SD slot has the mechanical switch
+    pub fn is_host_write_protected(&self) -> bool {
+        match self.base.host.as_ref() {
+            Some(host) if host.config.card_type == MCIHostCardType::StandardSD => {
+                host.dev.card_is_write_protected()
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the whole card refuses write and erase
+    pub fn is_read_only(&self) -> bool {
+        self.is_permanent_write_protected()
+            || self.is_temporary_write_protected()
+            || self.is_host_write_protected()
+    }
+
+    /// Write protect group size in blocks, 0 if group write protection is not supported
+    pub fn write_protect_group_size(&self) -> u32 {
+        if self.csd.flags & CsdFlags::WRITE_PROTECT_GROUP_ENABLED.bits() == 0
+            || self.csd.card_command_classes & SdCardCmdClass::WriteProtect.bits() == 0
+        {
+            return 0;
+        }
+
+        /* WP_GRP_SIZE is in erase sectors, SECTOR_SIZE is in write blocks */
+        let write_block_size = 1u32 << self.csd.write_block_length;
+        (self.csd.write_protect_group_size as u32 + 1)
+            * (self.csd.erase_sector_size as u32 + 1)
+            * write_block_size
+            / self.base.block_size
+    }
+
+    /// Set or clear temporary write protection of the whole card
+    pub fn temporary_write_protect_set(&mut self, enable: bool) -> MCIHostStatus {
+        if self.is_permanent_write_protected() {
+            return Err(MCIHostError::ReadOnly);
+        }
+
+        let mut raw_csd = self.raw_csd;
+        if enable {
+            raw_csd[0] |= SD_CSD_TMP_WRITE_PROTECT;
+        } else {
+            raw_csd[0] &= !SD_CSD_TMP_WRITE_PROTECT;
+        }
+
+        self.csd_program(&raw_csd)?;
+
+        self.raw_csd = raw_csd;
+        if enable {
+            self.csd.flags |= CsdFlags::TEMPORARY_WRITE_PROTECT.bits();
+        } else {
+            self.csd.flags &= !CsdFlags::TEMPORARY_WRITE_PROTECT.bits();
+        }
+        info!("temporary write protect set to {}", enable);
+
+        Ok(())
+    }
+
+    /// Set or clear write protection of the group containing `start_block`
+    pub fn group_write_protect_set(&mut self, start_block: u32, enable: bool) -> MCIHostStatus {
+        self.group_write_protect_check(start_block)?;
+
+        let index = if enable {
+            MCIHostCommonCmd::SetWriteProtect
+        } else {
+            MCIHostCommonCmd::ClearWriteProtect
+        };
+        self.write_protect_cmd_send(index, start_block)
+    }
+
+    /// Write protection bits of 32 groups from the group containing `start_block`,
+    /// bit 0 for the first group
+    pub fn group_write_protect_get(&mut self, start_block: u32) -> MCIHostStatus<u32> {
+        self.group_write_protect_check(start_block)?;
+        self.write_protect_status_send(start_block)
+    }
+
+    fn group_write_protect_check(&self, start_block: u32) -> MCIHostStatus {
+        if self.write_protect_group_size() == 0 {
+            info!("\r\nError: current card not support group write protection\r\n");
+            return Err(MCIHostError::CardNotSupport);
+        }
+        if start_block >= self.block_count {
+            return Err(MCIHostError::OutOfRange);
+        }
+        Ok(())
+    }
+
+    /// CMD 27
+    fn csd_program(&mut self, raw_csd: &[u32; 4]) -> MCIHostStatus {
+        if Err(MCIHostError::CardStatusIdle)
+            != self.polling_card_status_busy(SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT)
+        {
+            error!("Error: program csd failed, card status busy");
+            return Err(MCIHostError::PollingCardIdleFailed);
+        }
+
+        /* CSD is sent MSB first, the last byte is CRC7 and end bit */
+        let mut csd_data = raw_csd
+            .iter()
+            .rev()
+            .flat_map(|word| word.to_be_bytes())
+            .collect::<Vec<u8>>();
+        csd_data[15] = (crc7(&csd_data[..15]) << 1) | 0x1;
+
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+
+        let mut command = MCIHostCmd::new();
+
+        command.index_set(MCIHostCommonCmd::ProgramCsd as u32);
+        command.argument_set(0);
+        command.response_type_set(MCIHostResponseType::R1);
+        command.response_error_flags_set(MCIHostCardStatusFlag::ALL_ERROR_FLAG);
+
+        let mut data = MCIHostData::new();
+        data.block_size_set(csd_data.len());
+        data.block_count_set(1);
+        data.timeout_set(self.data_timeout(csd_data.len() as u32, 1, true));
+        let tx_buf = csd_data
+            .chunks_exact(4)
+            .map(|bytes| u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect::<Vec<u32>>();
+        data.tx_data_copy(&tx_buf)?;
+
+        let mut content = MCIHostTransfer::new();
+        content.set_cmd(Some(command));
+        content.set_data(Some(data));
+
+        if let Err(err) = host.dev.transfer_function(&mut content, host) {
+            let command = content.cmd().unwrap();
+            let response = command.response();
+            info!(
+                "\r\nError: send CMD27 failed with host error {:?}, response 0x{:x}\r\n",
+                err, response[0]
+            );
+            return Err(MCIHostError::TransferFailed);
+        }
+
+        let command = content.cmd().unwrap();
+        let response = command.response();
+        if response[0] & MCIHostCardStatusFlag::ALL_ERROR_FLAG.bits() != 0 {
+            info!(
+                "\r\nError: CMD27 response error, response 0x{:x}\r\n",
+                response[0]
+            );
+            return Err(MCIHostError::TransferFailed);
+        }
+
+        if Err(MCIHostError::CardStatusIdle)
+            != self.polling_card_status_busy(SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT)
+        {
+            return Err(MCIHostError::WaitWriteCompleteFailed);
+        }
+
+        Ok(())
+    }
+
+    /// CMD 28/29
+    fn write_protect_cmd_send(
+        &mut self,
+        index: MCIHostCommonCmd,
+        start_block: u32,
+    ) -> MCIHostStatus {
+        /* only SDSC supports group write protection, argument is byte address */
+        let address = start_block
+            .checked_mul(self.base.block_size)
+            .ok_or(MCIHostError::InvalidArgument)?;
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+
+        let mut command = MCIHostCmd::new();
+
+        command.index_set(index as u32);
+        command.argument_set(address);
+        command.response_type_set(MCIHostResponseType::R1b);
+        command.response_error_flags_set(MCIHostCardStatusFlag::ALL_ERROR_FLAG);
+
+        let mut content = MCIHostTransfer::new();
+        content.set_cmd(Some(command));
+
+        if let Err(err) = host.dev.transfer_function(&mut content, host) {
+            let command = content.cmd().unwrap();
+            let response = command.response();
+            info!(
+                "\r\nError: send CMD{} failed with host error {:?}, response 0x{:x}\r\n",
+                index as u32, err, response[0]
+            );
+            return Err(MCIHostError::TransferFailed);
+        }
+
+        let command = content.cmd().unwrap();
+        let response = command.response();
+        if response[0] & MCIHostCardStatusFlag::ALL_ERROR_FLAG.bits() != 0 {
+            info!(
+                "\r\nError: CMD{} response error, response 0x{:x}\r\n",
+                index as u32, response[0]
+            );
+            return Err(MCIHostError::TransferFailed);
+        }
+
+        if Err(MCIHostError::CardStatusIdle)
+            != self.polling_card_status_busy(SD_CARD_ACCESS_WAIT_IDLE_TIMEOUT)
+        {
+            return Err(MCIHostError::WaitWriteCompleteFailed);
+        }
+
+        Ok(())
+    }
+
+    /// CMD 30
+    fn write_protect_status_send(&mut self, start_block: u32) -> MCIHostStatus<u32> {
+        let address = start_block
+            .checked_mul(self.base.block_size)
+            .ok_or(MCIHostError::InvalidArgument)?;
+        let host = self.base.host.as_ref().ok_or(MCIHostError::HostNotReady)?;
+
+        let mut command = MCIHostCmd::new();
+
+        command.index_set(MCIHostCommonCmd::SendWriteProtect as u32);
+        command.argument_set(address);
+        command.response_type_set(MCIHostResponseType::R1);
+        command.response_error_flags_set(MCIHostCardStatusFlag::ALL_ERROR_FLAG);
+
+        let mut data = MCIHostData::new();
+        data.block_size_set(4);
+        data.block_count_set(1);
+        data.rx_data_alloc(1)?;
+
+        let mut content = MCIHostTransfer::new();
+        content.set_cmd(Some(command));
+        content.set_data(Some(data));
+
+        if let Err(err) = host.dev.transfer_function(&mut content, host) {
+            let command = content.cmd().unwrap();
+            let response = command.response();
+            info!(
+                "\r\nError: send CMD30 failed with host error {:?}, response 0x{:x}\r\n",
+                err, response[0]
+            );
+            return Err(MCIHostError::TransferFailed);
+        }
+
+        let command = content.cmd().unwrap();
+        let response = command.response();
+        if response[0] & MCIHostCardStatusFlag::ALL_ERROR_FLAG.bits() != 0 {
+            info!(
+                "\r\nError: CMD30 response error, response 0x{:x}\r\n",
+                response[0]
+            );
+            return Err(MCIHostError::TransferFailed);
+        }
+
+        /* write protection bits are sent MSB first */
+        let rx_data = content.data_mut().unwrap().rx_data_mut().unwrap();
+        let _ = host.dev.convert_data_to_little_endian(
+            rx_data,
+            1,
+            MCIHostDataPacketFormat::MSBFirst,
+            host,
+        );
+
+        Ok(rx_data[0])
+    }
+}
@@ -4,8 +4,6 @@
 pub const SZ_2M: usize = 2 * 1024 * 1024;
 /// Max size can be managed by Tlsf pool
 pub const MAX_POOL_SIZE: usize = SZ_2M;
-/// OSA semaphore handle size
-pub const OSA_SEM_HANDLE_SIZE: usize = 8;
 
 /// Transfer event flags
 /// Command transfer completed successfully
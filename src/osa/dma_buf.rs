@@ -0,0 +1,163 @@
+//! Typed buffer for DMA, built on [`PoolBuffer`].
+//!
+//! Provides [`DmaBuf`] - element length tracked separately from capacity,
+//! with bus address resolved once at allocation
+use core::{
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+    slice::{from_raw_parts, from_raw_parts_mut},
+};
+
+use alloc::vec::Vec;
+use bytemuck::Pod;
+
+use super::{err::FMempError, pool_buffer::PoolBuffer};
+use crate::dma::DmaRegion;
+use crate::mmap;
+
+/// DmaBuf definition
+pub struct DmaBuf<T: Pod> {
+    buf: PoolBuffer,
+    len: usize,
+    bus_addr: u64,
+    _marker: PhantomData<T>,
+}
+
+/// DmaBuf 独占其内存, 可以在线程间转移
+unsafe impl<T: Pod + Send> Send for DmaBuf<T> {}
+unsafe impl<T: Pod + Sync> Sync for DmaBuf<T> {}
+
+impl<T: Pod> DmaBuf<T> {
+    /// Alloc an empty DmaBuf which can hold `capacity` elements
+    pub fn new(capacity: usize, align: usize) -> Result<Self, FMempError> {
+        let size = capacity * size_of::<T>();
+        let align = align.max(align_of::<T>());
+        let mut buf = PoolBuffer::new(size, align).map_err(|_| FMempError::BadMalloc)?;
+        // Pod 允许任意位模式, 但不允许读取未初始化内存
+        buf.clear();
+        let bus_addr = mmap(buf.addr());
+
+        Ok(Self {
+            buf,
+            len: 0,
+            bus_addr,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Alloc a DmaBuf of `len` zeroed elements
+    pub fn zeroed(len: usize, align: usize) -> Result<Self, FMempError> {
+        let mut buf = Self::new(len, align)?;
+        buf.len = len;
+        Ok(buf)
+    }
+
+    /// Capacity in elements
+    pub fn capacity(&self) -> usize {
+        self.buf.size() / size_of::<T>()
+    }
+
+    /// Length in elements
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Resize to `len` elements, new elements are zeroed
+    pub fn resize(&mut self, len: usize) -> Result<(), FMempError> {
+        if len > self.capacity() {
+            return Err(FMempError::NotEnoughSpace);
+        }
+
+        if len > self.len {
+            unsafe {
+                let start = self.as_mut_ptr().add(self.len) as *mut u8;
+                start.write_bytes(0, (len - self.len) * size_of::<T>());
+            }
+        }
+        self.len = len;
+        Ok(())
+    }
+
+    /// Clear buffer, leaving 0s at original places
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.len = 0;
+    }
+
+    /// Replace contents with `src`
+    pub fn copy_from_slice(&mut self, src: &[T]) -> Result<(), FMempError> {
+        if src.len() > self.capacity() {
+            return Err(FMempError::NotEnoughSpace);
+        }
+
+        self.len = src.len();
+        self.as_mut_slice().copy_from_slice(src);
+        Ok(())
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { from_raw_parts(self.as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { from_raw_parts_mut(self.as_mut_ptr(), self.len) }
+    }
+
+    pub fn as_ptr(&self) -> *const T {
+        self.buf.addr().as_ptr() as *const T
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.buf.addr().as_ptr() as *mut T
+    }
+
+    /// Get addr
+    pub fn addr(&self) -> NonNull<u8> {
+        self.buf.addr()
+    }
+
+    /// Bus address of the first element
+    pub fn bus_addr(&self) -> u64 {
+        self.bus_addr
+    }
+
+    /// Region of the elements in use, for cache maintenance
+    pub fn region(&self) -> DmaRegion {
+        DmaRegion::new(self.addr(), self.len * size_of::<T>())
+    }
+
+    /// Construct a Vec<T> from elements in use
+    pub fn to_vec(&self) -> Vec<T> {
+        self.as_slice().to_vec()
+    }
+}
+
+impl<T: Pod> fmt::Debug for DmaBuf<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DmaBuf")
+            .field("addr", &self.addr())
+            .field("bus_addr", &self.bus_addr)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl<T: Pod> Deref for DmaBuf<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T: Pod> DerefMut for DmaBuf<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
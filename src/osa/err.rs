@@ -6,7 +6,15 @@ pub enum FMempError {
     // PoolBuffer related errors
     NotEnoughSpace, // PoolBuffer size too small to copy contents from a slice
     SizeNotAligned, // PoolBuffer size isn't aligned to size::T
+    AlreadyInUse,   // Memory source can not change after init or first allocation
 }
 
 #[allow(unused)]
 pub type FMempStatus<T = ()> = Result<T, FMempError>;
+
+/// OSA 接口错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OsaError {
+    Timeout,     // Wait timeout
+    AlreadyInit, // OSA can not change after init or first use
+}
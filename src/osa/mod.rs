@@ -6,21 +6,33 @@ use core::{
     time::Duration,
 };
 
-use alloc::{boxed::Box, sync::Arc};
-use consts::{MAX_POOL_SIZE, SDMMC_OSA_EVENT_FLAG_AND};
-use err::FMempError;
+use alloc::boxed::Box;
+use consts::SDMMC_OSA_EVENT_FLAG_AND;
 use lazy_static::*;
 use rlsf::Tlsf;
-use spin::Mutex;
+use spin::{Mutex, Once};
 
-use crate::{mci, sleep};
+use crate::tools::Deadline;
 
 pub mod consts;
+pub mod dma_buf;
 mod err;
 pub mod pool_buffer;
 
+pub use err::{FMempError, OsaError};
+
 /// Memory menaged by Tlsf pool
-static mut POOL: [MaybeUninit<u8>; MAX_POOL_SIZE] = [MaybeUninit::uninit(); MAX_POOL_SIZE];
+#[cfg(feature = "builtin-pool")]
+static mut POOL: [MaybeUninit<u8>; consts::MAX_POOL_SIZE] =
+    [MaybeUninit::uninit(); consts::MAX_POOL_SIZE];
+
+/// DMA 内存提供者, 由内核提供物理连续、不可缓存或硬件一致的内存
+pub trait DmaAllocator: Send + Sync {
+    /// Alloc memory for `layout`, None if out of memory
+    fn alloc(&self, layout: Layout) -> Option<NonNull<u8>>;
+    /// Free memory returned by `alloc` with the same `layout`
+    fn dealloc(&self, addr: NonNull<u8>, layout: Layout);
+}
 
 /// Tlsf controller
 pub struct FMemp<'a> {
@@ -32,8 +44,13 @@ lazy_static! {
     /// Global memory pool manager
     pub static ref GLOBAL_FMEMP: Mutex<Box<FMemp<'static>>> =
         Mutex::new(Box::new(FMemp::new()));
+    /// 内核注册的 DMA 内存提供者, 未注册时使用 Tlsf pool
+    static ref DMA_ALLOCATOR: Mutex<Option<&'static dyn DmaAllocator>> = Mutex::new(None);
 }
 
+/// 是否已经分配过内存, 分配后不能再切换内存来源
+static DMA_MEM_IN_USE: AtomicBool = AtomicBool::new(false);
+
 impl<'a> FMemp<'a> {
     /// Constructor
     pub fn new() -> Self {
@@ -43,17 +60,24 @@ impl<'a> FMemp<'a> {
         }
     }
 
-    unsafe fn init(&mut self) {
-        self.tlsf_ptr.insert_free_block(&mut POOL[..]);
+    /// 重复初始化直接返回, 避免同一块内存被多次插入 Tlsf
+    unsafe fn init(&mut self, pool: &'static mut [MaybeUninit<u8>]) {
+        if self.is_ready {
+            return;
+        }
+        self.tlsf_ptr.insert_free_block(pool);
         self.is_ready = true;
     }
 
-    unsafe fn alloc_aligned(
-        &mut self,
-        size: usize,
-        align: usize,
-    ) -> Result<NonNull<u8>, FMempError> {
-        let layout = Layout::from_size_align_unchecked(size, align);
+    unsafe fn alloc_aligned(&mut self, layout: Layout) -> Result<NonNull<u8>, FMempError> {
+        if !self.is_ready {
+            #[cfg(feature = "builtin-pool")]
+            self.init(&mut *core::ptr::addr_of_mut!(POOL));
+            /* 没有内置内存池时必须先调用 osa_init_with_pool 或注册 DmaAllocator */
+            #[cfg(not(feature = "builtin-pool"))]
+            return Err(FMempError::InitTlsfError);
+        }
+
         if let Some(result) = self.tlsf_ptr.allocate(layout) {
             Ok(result)
         } else {
@@ -61,38 +85,133 @@ impl<'a> FMemp<'a> {
         }
     }
 
-    unsafe fn dealloc(&mut self, addr: NonNull<u8>, size: usize) {
-        self.tlsf_ptr.deallocate(addr, size);
+    /// Tlsf 从块头部获取大小, 释放时只需要对齐
+    unsafe fn dealloc(&mut self, addr: NonNull<u8>, layout: Layout) {
+        self.tlsf_ptr.deallocate(addr, layout.align());
     }
 }
 
-/// Init memory pool with size of ['MAX_POOL_SIZE']
+/// Init memory pool with size of ['MAX_POOL_SIZE'], do nothing if already inited
+/// or the `builtin-pool` feature is disabled
 pub fn osa_init() {
+    #[cfg(feature = "builtin-pool")]
     unsafe {
-        GLOBAL_FMEMP.lock().init();
+        GLOBAL_FMEMP
+            .lock()
+            .init(&mut *core::ptr::addr_of_mut!(POOL));
+    }
+}
+
+/// Init memory pool with a region supplied by kernel instead of the builtin one,
+/// should be called before any [`SdCard`](crate::sd::SdCard) is created
+pub fn osa_init_with_pool(pool: &'static mut [MaybeUninit<u8>]) -> Result<(), FMempError> {
+    let mut fmemp = GLOBAL_FMEMP.lock();
+    if fmemp.is_ready {
+        return Err(FMempError::AlreadyInUse);
+    }
+    unsafe {
+        fmemp.init(pool);
+    }
+    Ok(())
+}
+
+/// Register a DMA memory provider, should be called before any allocation. IDMAC
+/// descriptors and all command data buffers are allocated from it
+pub fn osa_dma_allocator_set(allocator: &'static dyn DmaAllocator) -> Result<(), FMempError> {
+    let mut dma_allocator = DMA_ALLOCATOR.lock();
+    if DMA_MEM_IN_USE.load(Ordering::Acquire) {
+        return Err(FMempError::AlreadyInUse);
     }
+    *dma_allocator = Some(allocator);
+    Ok(())
 }
 
-/// Alloc 'size' bytes space, aligned to 64 KiB by default
+/// Alloc 'size' bytes space, aligned to usize
 pub fn osa_alloc(size: usize) -> Result<NonNull<u8>, FMempError> {
-    unsafe { GLOBAL_FMEMP.lock().alloc_aligned(size, size_of::<usize>()) }
+    osa_alloc_aligned(size, size_of::<usize>())
 }
 
 /// Alloc 'size' bytes space, aligned to 'align' bytes
 pub fn osa_alloc_aligned(size: usize, align: usize) -> Result<NonNull<u8>, FMempError> {
-    unsafe { GLOBAL_FMEMP.lock().alloc_aligned(size, align) }
+    let layout = Layout::from_size_align(size, align).map_err(|_| FMempError::InvalidBuf)?;
+
+    let dma_allocator = DMA_ALLOCATOR.lock();
+    DMA_MEM_IN_USE.store(true, Ordering::Release);
+    match *dma_allocator {
+        Some(allocator) => allocator.alloc(layout).ok_or(FMempError::BadMalloc),
+        None => unsafe { GLOBAL_FMEMP.lock().alloc_aligned(layout) },
+    }
 }
 
-/// Dealloc 'size' bytes space from 'addr'
-pub fn osa_dealloc(addr: NonNull<u8>, size: usize) {
-    unsafe {
-        GLOBAL_FMEMP.lock().dealloc(addr, size);
+/// Dealloc 'size' bytes space aligned to 'align' from 'addr'
+pub fn osa_dealloc(addr: NonNull<u8>, size: usize, align: usize) {
+    let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
+    match *DMA_ALLOCATOR.lock() {
+        Some(allocator) => allocator.dealloc(addr, layout),
+        None => unsafe { GLOBAL_FMEMP.lock().dealloc(addr, layout) },
+    }
+}
+
+/// 由嵌入的操作系统实现的 OSA 接口, 未注册时使用忙等实现 [`SpinOsa`]
+pub trait Osa: Send + Sync {
+    /// Create an event group, each SDIF instance waits its transfers on one
+    fn event_create(&self) -> Box<dyn OsaEvent>;
+
+    /// Release the semaphore, may be called in interrupt context
+    fn sem_post(&self);
+    fn sem_wait(&self, timeout: Duration) -> Result<(), OsaError>;
+
+    /// Create a mutex, each SDIF instance serializes its commands with one
+    fn mutex_create(&self) -> Box<dyn OsaMutex>;
+}
+
+/// 由 [`Osa::event_create`] 创建的事件组
+pub trait OsaEvent: Send + Sync {
+    /// Set `events` in the event group, may be called in interrupt context
+    fn set(&self, events: u32);
+    /// Wait `events` with [`SDMMC_OSA_EVENT_FLAG_AND`] or [`consts::SDMMC_OSA_EVENT_FLAG_OR`],
+    /// return all events set at that time
+    fn wait(&self, events: u32, flag: u32, timeout: Duration) -> Result<u32, OsaError>;
+    fn clear(&self, events: u32);
+    fn get(&self) -> u32;
+}
+
+/// 由 [`Osa::mutex_create`] 创建的互斥锁, 等待时应让出 CPU
+pub trait OsaMutex: Send + Sync {
+    /// Lock the mutex, [`Duration::MAX`] waits forever
+    fn lock(&self, timeout: Duration) -> Result<(), OsaError>;
+    fn unlock(&self);
+}
+
+/// 事件组是否满足等待条件
+fn event_ready(set: u32, events: u32, flag: u32) -> bool {
+    if flag & SDMMC_OSA_EVENT_FLAG_AND != 0 {
+        set & events == events
+    } else {
+        set & events != 0
+    }
+}
+
+/// 忙等直到 `ready` 成立或超时
+fn spin_until(timeout: Duration, mut ready: impl FnMut() -> bool) -> Result<(), OsaError> {
+    let deadline = Deadline::after(timeout);
+
+    loop {
+        let expired = deadline.is_expired();
+        if ready() {
+            return Ok(());
+        }
+
+        if expired {
+            return Err(OsaError::Timeout);
+        }
+
+        core::hint::spin_loop();
     }
 }
 
 pub struct OSAEvent {
     event_flag: AtomicU32,
-    notification: AtomicBool,
 }
 
 impl Default for OSAEvent {
@@ -105,68 +224,214 @@ impl OSAEvent {
     pub const fn new() -> Self {
         Self {
             event_flag: AtomicU32::new(0),
-            notification: AtomicBool::new(false),
         }
     }
 
     pub fn osa_event_set(&self, event_type: u32) {
         self.event_flag.fetch_or(event_type, Ordering::SeqCst);
-        self.notification.store(true, Ordering::Release);
     }
 
-    pub fn osa_event_wait(&self, event_type: u32, timeout_ticks: u32) -> Result<u32, &'static str> {
-        let mut ticks = 0;
+    pub fn osa_event_wait(
+        &self,
+        event_type: u32,
+        flag: u32,
+        timeout: Duration,
+    ) -> Result<u32, OsaError> {
+        let mut events = 0;
+        spin_until(timeout, || {
+            events = self.event_flag.load(Ordering::SeqCst);
+            event_ready(events, event_type, flag)
+        })?;
+        Ok(events)
+    }
 
-        loop {
-            if self.notification.load(Ordering::Acquire) {
-                let events = self.event_flag.load(Ordering::SeqCst);
-                if events & event_type != 0 {
-                    self.notification.store(false, Ordering::Release);
-                    return Ok(events);
-                }
-            }
+    pub fn osa_event_clear(&self, event_type: u32) {
+        self.event_flag.fetch_and(!event_type, Ordering::SeqCst);
+    }
 
-            if ticks >= timeout_ticks {
-                return Err("timeout");
-            }
+    pub fn osa_event_get(&self) -> u32 {
+        self.event_flag.load(Ordering::SeqCst)
+    }
+}
+
+impl OsaEvent for OSAEvent {
+    fn set(&self, events: u32) {
+        self.osa_event_set(events);
+    }
+
+    fn wait(&self, events: u32, flag: u32, timeout: Duration) -> Result<u32, OsaError> {
+        self.osa_event_wait(events, flag, timeout)
+    }
+
+    fn clear(&self, events: u32) {
+        self.osa_event_clear(events);
+    }
 
-            ticks += 1;
-            #[cfg(feature = "pio")]
-            sleep(Duration::from_millis(2));
+    fn get(&self) -> u32 {
+        self.osa_event_get()
+    }
+}
+
+/// 计数信号量
+pub struct OSASemaphore {
+    count: AtomicU32,
+}
 
-            core::hint::spin_loop();
+impl OSASemaphore {
+    pub const fn new(count: u32) -> Self {
+        Self {
+            count: AtomicU32::new(count),
         }
     }
 
-    pub fn osa_event_clear(&self, event_type: u32) {
-        self.event_flag.fetch_and(!event_type, Ordering::SeqCst);
+    pub fn osa_sem_post(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn osa_sem_wait(&self, timeout: Duration) -> Result<(), OsaError> {
+        spin_until(timeout, || {
+            self.count
+                .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| {
+                    count.checked_sub(1)
+                })
+                .is_ok()
+        })
+    }
+}
 
-        if self.event_flag.load(Ordering::SeqCst) == 0 {
-            self.notification.store(false, Ordering::Release);
+/// 不可重入的互斥锁
+pub struct OSAMutex {
+    locked: AtomicBool,
+}
+
+impl Default for OSAMutex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OSAMutex {
+    pub const fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
         }
     }
 
-    pub fn osa_event_get(&self) -> u32 {
-        self.event_flag.load(Ordering::SeqCst)
+    pub fn osa_mutex_lock(&self, timeout: Duration) -> Result<(), OsaError> {
+        spin_until(timeout, || {
+            self.locked
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        })
+    }
+
+    pub fn osa_mutex_unlock(&self) {
+        self.locked.store(false, Ordering::Release);
     }
 }
 
-lazy_static! {
-    static ref OSA_EVENT: OSAEvent = OSAEvent::new();
+impl OsaMutex for OSAMutex {
+    fn lock(&self, timeout: Duration) -> Result<(), OsaError> {
+        self.osa_mutex_lock(timeout)
+    }
+
+    fn unlock(&self) {
+        self.osa_mutex_unlock();
+    }
+}
+
+/// 默认的忙等 OSA 实现, 适用于裸机环境
+pub struct SpinOsa {
+    sem: OSASemaphore,
+}
+
+impl Default for SpinOsa {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpinOsa {
+    pub const fn new() -> Self {
+        Self {
+            sem: OSASemaphore::new(0),
+        }
+    }
 }
 
-pub fn osa_event_set(event_type: u32) {
-    OSA_EVENT.osa_event_set(event_type);
+impl Osa for SpinOsa {
+    fn event_create(&self) -> Box<dyn OsaEvent> {
+        Box::new(OSAEvent::new())
+    }
+
+    fn sem_post(&self) {
+        self.sem.osa_sem_post();
+    }
+
+    fn sem_wait(&self, timeout: Duration) -> Result<(), OsaError> {
+        self.sem.osa_sem_wait(timeout)
+    }
+
+    fn mutex_create(&self) -> Box<dyn OsaMutex> {
+        Box::new(OSAMutex::new())
+    }
 }
 
-pub fn osa_event_wait(event_type: u32, timeout: u32) -> Result<(), &'static str> {
-    OSA_EVENT.osa_event_wait(event_type, timeout).map(|_| ())
+static SPIN_OSA: SpinOsa = SpinOsa::new();
+
+/// 当前使用的 OSA 实现, 首次使用后不能再更换
+static OSA: Once<&'static dyn Osa> = Once::new();
+
+fn osa() -> &'static dyn Osa {
+    *OSA.call_once(|| &SPIN_OSA)
 }
 
-pub fn osa_event_get() -> u32 {
-    OSA_EVENT.osa_event_get()
+/// Register the OSA implemented by kernel, should be called before any [`SdCard`](crate::sd::SdCard) is created
+pub fn osa_set(implement: &'static dyn Osa) -> Result<(), OsaError> {
+    let mut registered = false;
+    OSA.call_once(|| {
+        registered = true;
+        implement
+    });
+
+    if registered {
+        Ok(())
+    } else {
+        Err(OsaError::AlreadyInit)
+    }
+}
+
+pub(crate) fn osa_event_create() -> Box<dyn OsaEvent> {
+    osa().event_create()
+}
+
+pub fn osa_sem_post() {
+    osa().sem_post();
+}
+
+pub fn osa_sem_wait(timeout: Duration) -> Result<(), OsaError> {
+    osa().sem_wait(timeout)
 }
 
-pub fn osa_event_clear(event_type: u32) {
-    OSA_EVENT.osa_event_clear(event_type);
+pub(crate) fn osa_mutex_create() -> Box<dyn OsaMutex> {
+    osa().mutex_create()
+}
+
+/// Lock `mutex`, unlocked when the guard is dropped
+pub(crate) fn osa_mutex_lock(
+    mutex: &dyn OsaMutex,
+    timeout: Duration,
+) -> Result<OsaMutexGuard<'_>, OsaError> {
+    mutex.lock(timeout)?;
+    Ok(OsaMutexGuard { mutex })
+}
+
+pub(crate) struct OsaMutexGuard<'a> {
+    mutex: &'a dyn OsaMutex,
+}
+
+impl Drop for OsaMutexGuard<'_> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
 }
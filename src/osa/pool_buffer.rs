@@ -7,6 +7,7 @@ use core::{
 };
 
 use alloc::vec::Vec;
+use bytemuck::Pod;
 use log::error;
 
 use super::{err::FMempError, osa_alloc_aligned, osa_dealloc};
@@ -18,6 +19,11 @@ pub struct PoolBuffer {
     align: usize,
 }
 
+/// PoolBuffer 独占其内存, 可以在线程间转移
+unsafe impl Send for PoolBuffer {}
+/// 共享引用只能读取内容
+unsafe impl Sync for PoolBuffer {}
+
 impl PoolBuffer {
     /// Alloc a PoolBuffer, where size is buffer size in bytes
     pub fn new(size: usize, align: usize) -> Result<Self, &'static str> {
@@ -47,51 +53,36 @@ impl PoolBuffer {
         Ok(())
     }
 
-    /// Construct a &[T] from self
-    pub fn as_slice<T>(&self) -> Result<&[T], FMempError> {
-        let size = size_of::<T>();
-        if self.size() % size != 0 {
-            return Err(FMempError::SizeNotAligned);
-        }
-
-        unsafe {
-            let result = from_raw_parts(self.addr.as_ptr() as *const T, self.size() / size);
-            Ok(result)
-        }
+    /// Construct a &[T] from self, size and addr should be aligned to T
+    pub fn as_slice<T: Pod>(&self) -> Result<&[T], FMempError> {
+        let bytes = unsafe { from_raw_parts(self.addr.as_ptr() as *const u8, self.size) };
+        bytemuck::try_cast_slice(bytes).map_err(|_| FMempError::SizeNotAligned)
     }
 
-    pub fn as_slice_in_len<T>(&self, len: usize) -> Result<&[T], FMempError> {
+    pub fn as_slice_in_len<T: Pod>(&self, len: usize) -> Result<&[T], FMempError> {
         if len * size_of::<T>() > self.size {
             error!("Acquiring length to big for this PoolBuffer");
             return Err(FMempError::NotEnoughSpace);
         }
 
-        unsafe {
-            let result = from_raw_parts(self.addr.as_ptr() as *const T, len);
-            Ok(result)
-        }
+        let bytes =
+            unsafe { from_raw_parts(self.addr.as_ptr() as *const u8, len * size_of::<T>()) };
+        bytemuck::try_cast_slice(bytes).map_err(|_| FMempError::SizeNotAligned)
     }
 
-    /// Construct a &mut [T] from self
-    pub fn as_slice_mut<T>(&self) -> Result<&[T], FMempError> {
-        let size = size_of::<T>();
-        if self.size() % size != 0 {
-            return Err(FMempError::SizeNotAligned);
-        }
-
-        unsafe {
-            let result = from_raw_parts_mut(self.addr.as_ptr() as *mut T, self.size() / size);
-            Ok(result)
-        }
+    /// Construct a &mut [T] from self, size and addr should be aligned to T
+    pub fn as_slice_mut<T: Pod>(&mut self) -> Result<&mut [T], FMempError> {
+        let bytes = unsafe { from_raw_parts_mut(self.addr.as_ptr(), self.size) };
+        bytemuck::try_cast_slice_mut(bytes).map_err(|_| FMempError::SizeNotAligned)
     }
 
-    /// Construct a Vec<u32> from self
-    pub fn to_vec<T: Clone>(&self) -> Result<Vec<T>, FMempError> {
+    /// Construct a Vec<T> from self
+    pub fn to_vec<T: Pod>(&self) -> Result<Vec<T>, FMempError> {
         let slice = self.as_slice::<T>()?;
         Ok(slice.to_vec())
     }
 
-    pub fn to_vec_in_len<T: Clone>(&self, len: usize) -> Result<Vec<T>, FMempError> {
+    pub fn to_vec_in_len<T: Pod>(&self, len: usize) -> Result<Vec<T>, FMempError> {
         let slice = self.as_slice_in_len::<T>(len)?;
         Ok(slice.to_vec())
     }
@@ -116,15 +107,17 @@ impl PoolBuffer {
 
 impl Drop for PoolBuffer {
     fn drop(&mut self) {
-        osa_dealloc(self.addr, self.align);
+        osa_dealloc(self.addr, self.size, self.align);
     }
 }
 
 impl Into<Vec<u32>> for PoolBuffer {
+    /// 末尾不足一个字的字节补 0
     fn into(self) -> Vec<u32> {
+        let mut words = alloc::vec![0u32; self.size.div_ceil(4)];
         unsafe {
-            let slice = from_raw_parts(self.addr.as_ptr() as *const u32, self.size / 4);
-            slice.to_vec()
+            copy_nonoverlapping(self.addr.as_ptr(), words.as_mut_ptr() as *mut u8, self.size);
         }
+        words
     }
 }
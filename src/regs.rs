@@ -1,6 +1,7 @@
 #![allow(unused)]
 
 use crate::sleep;
+use crate::tools::Deadline;
 use bitflags::{bitflags, Flags};
 use core::{marker::PhantomData, ops, ptr::NonNull, time::Duration};
 use log::info;
@@ -68,6 +69,10 @@ pub struct Reg<E: RegError> {
     _marker: PhantomData<E>,
 }
 
+/// 寄存器均为 volatile 访问的 MMIO, 基地址可以在核间共享
+unsafe impl<E: RegError> Send for Reg<E> {}
+unsafe impl<E: RegError> Sync for Reg<E> {}
+
 impl<E: RegError> Reg<E> {
     pub fn new(addr: NonNull<u8>) -> Self {
         Self {
@@ -119,29 +124,38 @@ impl<E: RegError> Reg<E> {
         &self,
         f: F,
         interval: Duration,
-        try_count: Option<usize>,
+        timeout: Option<Duration>,
     ) -> Result<(), E> {
-        for _ in 0..try_count.unwrap_or(usize::MAX) {
+        let deadline = timeout.map(Deadline::after);
+        loop {
+            /* 超时后仍再检查一次, 避免被抢占导致误判超时 */
+            let expired = deadline.is_some_and(|d| d.is_expired());
             if f(self.read_reg::<R>()) {
                 return Ok(());
             }
+            if expired {
+                return Err(E::timeout());
+            }
 
             sleep(interval);
         }
-        Err(E::timeout())
     }
 
     pub fn retry_for<R: FlagReg, F: Fn(R) -> bool>(
         &self,
         f: F,
-        try_count: Option<usize>,
+        timeout: Option<Duration>,
     ) -> Result<(), E> {
-        for _ in 0..try_count.unwrap_or(usize::MAX) {
+        let deadline = timeout.map(Deadline::after);
+        loop {
+            let expired = deadline.is_some_and(|d| d.is_expired());
             if f(self.read_reg::<R>()) {
                 return Ok(());
             }
+            if expired {
+                return Err(E::timeout());
+            }
         }
-        Err(E::timeout())
     }
 }
 
@@ -1,3 +1,26 @@
+use core::time::Duration;
+
+use crate::now;
+
+/// 基于 Kernel::now 单调时钟的超时截止时刻
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline(Duration);
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self {
+        Deadline(now().saturating_add(timeout))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        now() >= self.0
+    }
+
+    #[allow(unused)]
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_sub(now())
+    }
+}
+
 /// 将每个16位半字互换
 pub fn swap_half_word_byte_sequence_u32(value: u32) -> u32 {
     ((value & 0x0000FFFF) << 16) | ((value & 0xFFFF0000) >> 16)
@@ -11,6 +34,21 @@ pub fn swap_word_byte_sequence_u32(value: u32) -> u32 {
         | ((value & 0xFF000000) >> 24)
 }
 
+/// 计算 CRC7 (x^7 + x^3 + 1)
+pub fn crc7(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        for i in (0..8).rev() {
+            let bit = ((byte >> i) & 0x1) ^ ((crc >> 6) & 0x1);
+            crc = (crc << 1) & 0x7F;
+            if bit != 0 {
+                crc ^= 0x09;
+            }
+        }
+    }
+    crc
+}
+
 // pub fn u8_to_u32_slice(bytes: &Vec<u8>) -> Vec<u32> {
 //     assert!(bytes.len() % 4 == 0, "字节数组长度必须是4的倍数");
 